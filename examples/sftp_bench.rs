@@ -0,0 +1,150 @@
+//! Measures SFTP upload throughput for several buffer sizes.
+//!
+//! Point it at a local (or remote) SSH server with a scratch account:
+//!
+//! ```text
+//! cargo run --release --example sftp_bench -- <host> <port> <username> <password> [size_mb] [remote_dir]
+//! ```
+//!
+//! The test file is first written to `<remote_dir>` with a plain 8 KiB write
+//! loop, the transfer loop flash used to have. Then, for every buffer size, it
+//! is written through [`upload::pipelined_copy`] and uploaded with
+//! [`upload::upload_file`], which includes hashing and verification.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+
+use flash::loading::TransferProgress;
+use flash::upload::{self, SshTarget, UploadOptions};
+
+const BUFFER_SIZES: [usize; 5] = [8 * 1024, 32 * 1024, 128 * 1024, 256 * 1024, 1024 * 1024];
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 4 {
+        eprintln!("Usage: sftp_bench <host> <port> <username> <password> [size_mb] [remote_dir]");
+        process::exit(2);
+    }
+    let target = SshTarget {
+        ip: args[0].clone(),
+        port: args[1].parse().context("Invalid port")?,
+        username: args[2].clone(),
+        password: args[3].clone(),
+    };
+    let size_mb: u64 = args.get(4).map_or(Ok(64), |s| s.parse())?;
+    let remote_dir = args.get(5).cloned().unwrap_or_else(|| "/tmp".to_string());
+
+    let local_path = env::temp_dir().join("flash-bench.bin");
+    write_test_file(&local_path, size_mb * 1024 * 1024)?;
+    let local = local_path.to_string_lossy().into_owned();
+    let size = fs::metadata(&local_path)?.len();
+    let remote = format!("{}/flash-bench.bin", remote_dir.trim_end_matches('/'));
+
+    let session = upload::connect(&target)?;
+    let sftp = session.sftp()?;
+    println!(
+        "Uploading {} to {}:{}\n",
+        HumanBytes(size),
+        target.ip,
+        remote
+    );
+    let baseline = timed(|| {
+        let mut file = File::open(&local)?;
+        let mut remote_file = sftp.create(Path::new(&remote))?;
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            remote_file.write_all(&buffer[..n])?;
+        }
+        Ok(())
+    })?;
+    println!(
+        "Baseline (8 KiB buffer, no read-ahead): {}\n",
+        throughput(size, baseline)
+    );
+    println!(
+        "{:>10}  {:>14}  {:>14}",
+        "buffer", "pipelined", "upload_file"
+    );
+
+    for buffer_size in BUFFER_SIZES {
+        let pipelined = timed(|| {
+            let mut remote_file = sftp.create(Path::new(&remote))?;
+            upload::pipelined_copy(File::open(&local)?, &mut remote_file, buffer_size, |_| {})?;
+            Ok(())
+        })?;
+
+        // Otherwise upload_file skips the identical file
+        sftp.unlink(Path::new(&remote))?;
+        let options = UploadOptions {
+            buffer_size,
+            ..UploadOptions::default()
+        };
+        let full = timed(|| {
+            let progress = TransferProgress::new();
+            upload::upload_file(&session, &local, &remote, &options, &progress)?;
+            progress.finish_and_clear();
+            Ok(())
+        })?;
+        sftp.unlink(Path::new(&remote))?;
+
+        println!(
+            "{:>10}  {:>14}  {:>14}",
+            HumanBytes(buffer_size as u64).to_string(),
+            throughput(size, pipelined),
+            throughput(size, full)
+        );
+    }
+
+    fs::remove_file(&local_path)?;
+    Ok(())
+}
+
+/// Writes `size` bytes of incompressible data to `path`.
+fn write_test_file(path: &Path, size: u64) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut written = 0;
+    while written < size {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let bytes = state.to_le_bytes();
+        let n = (size - written).min(bytes.len() as u64) as usize;
+        writer.write_all(&bytes[..n])?;
+        written += n as u64;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn timed(f: impl FnOnce() -> Result<()>) -> Result<Duration> {
+    let start = Instant::now();
+    f()?;
+    Ok(start.elapsed())
+}
+
+fn throughput(size: u64, elapsed: Duration) -> String {
+    format!(
+        "{}/s",
+        HumanBytes((size as f64 / elapsed.as_secs_f64()) as u64)
+    )
+}
@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssh2::{Session, Sftp};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::remote::{self, shell_quote};
+
+/// Hash used to verify that an uploaded file matches the local one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    /// 64-bit XXH3, fast but not cryptographic.
+    Xxh3,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "blake3",
+            ChecksumAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    /// Length of the hex digest.
+    pub fn hex_len(&self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha256 | ChecksumAlgorithm::Blake3 => 64,
+            ChecksumAlgorithm::Xxh3 => 16,
+        }
+    }
+
+    /// Server-side tools printing the digest of a file, tried in order.
+    pub fn remote_tools(&self) -> &'static [&'static str] {
+        match self {
+            ChecksumAlgorithm::Sha256 => &["sha256sum", "shasum -a 256"],
+            ChecksumAlgorithm::Blake3 => &["b3sum"],
+            ChecksumAlgorithm::Xxh3 => &["xxhsum -H3"],
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            ChecksumAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+        }
+    }
+}
+
+/// Hex digest of everything `reader` yields, read `buffer_size` bytes at a time.
+pub fn hash_reader(
+    algorithm: ChecksumAlgorithm,
+    mut reader: impl Read,
+    buffer_size: usize,
+) -> Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// Passes writes through to `inner` while hashing them, for data that can
+/// only be read once.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(algorithm: ChecksumAlgorithm, inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(algorithm),
+        }
+    }
+
+    /// Hex digest of everything written so far.
+    pub fn finish(self) -> String {
+        self.hasher.finalize_hex()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn hash_file(algorithm: ChecksumAlgorithm, path: &str, buffer_size: usize) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    hash_reader(algorithm, file, buffer_size)
+}
+
+/// Extracts the digest from the output of one of the
+/// [`ChecksumAlgorithm::remote_tools`], e.g. `<hex>  <file>` or
+/// `XXH3_<hex>  <file>`.
+pub fn parse_tool_output(algorithm: ChecksumAlgorithm, output: &str) -> Option<String> {
+    let first = output.split_whitespace().next()?;
+    let digest = first
+        .strip_prefix("XXH3_")
+        .unwrap_or(first)
+        .to_ascii_lowercase();
+    let valid =
+        digest.len() == algorithm.hex_len() && digest.chars().all(|c| c.is_ascii_hexdigit());
+    valid.then_some(digest)
+}
+
+/// Shell command hashing `remote_path`, or only its first `len` bytes, with
+/// the first of the algorithm's tools that is installed.
+pub fn digest_command(algorithm: ChecksumAlgorithm, remote_path: &str, len: Option<u64>) -> String {
+    algorithm
+        .remote_tools()
+        .iter()
+        .map(|tool| match len {
+            Some(len) => format!(
+                "head -c {} {} | {} 2>/dev/null",
+                len,
+                shell_quote(remote_path),
+                tool
+            ),
+            None => format!("{} {} 2>/dev/null", tool, shell_quote(remote_path)),
+        })
+        .collect::<Vec<_>>()
+        .join(" || ")
+}
+
+/// Hashes `remote_path` on the server with one of the algorithm's tools.
+///
+/// Returns `None` when the server does not allow exec channels or has none of
+/// the tools installed.
+pub fn remote_digest_exec(
+    session: &Session,
+    algorithm: ChecksumAlgorithm,
+    remote_path: &str,
+) -> Option<String> {
+    digest_exec(session, algorithm, remote_path, None)
+}
+
+fn digest_exec(
+    session: &Session,
+    algorithm: ChecksumAlgorithm,
+    remote_path: &str,
+    len: Option<u64>,
+) -> Option<String> {
+    let command = digest_command(algorithm, remote_path, len);
+    match remote::exec_capture(session, &command) {
+        Ok(output) if output.success() => parse_tool_output(algorithm, &output.stdout),
+        Ok(output) => {
+            debug!(
+                "Remote {} failed with status {}",
+                algorithm.name(),
+                output.exit_status
+            );
+            None
+        }
+        Err(e) => {
+            debug!("Remote {} unavailable: {:#}", algorithm.name(), e);
+            None
+        }
+    }
+}
+
+/// Digest of `remote_path`, computed on the server if possible and otherwise
+/// by reading the file back over SFTP.
+pub fn remote_digest(
+    session: &Session,
+    sftp: &Sftp,
+    algorithm: ChecksumAlgorithm,
+    remote_path: &str,
+    buffer_size: usize,
+) -> Result<String> {
+    digest(session, sftp, algorithm, remote_path, None, buffer_size)
+}
+
+/// Like [`remote_digest`], covering only the first `len` bytes of
+/// `remote_path`, e.g. the part of a growing log that was downloaded.
+pub fn remote_prefix_digest(
+    session: &Session,
+    sftp: &Sftp,
+    algorithm: ChecksumAlgorithm,
+    remote_path: &str,
+    len: u64,
+    buffer_size: usize,
+) -> Result<String> {
+    digest(
+        session,
+        sftp,
+        algorithm,
+        remote_path,
+        Some(len),
+        buffer_size,
+    )
+}
+
+fn digest(
+    session: &Session,
+    sftp: &Sftp,
+    algorithm: ChecksumAlgorithm,
+    remote_path: &str,
+    len: Option<u64>,
+    buffer_size: usize,
+) -> Result<String> {
+    if let Some(digest) = digest_exec(session, algorithm, remote_path, len) {
+        return Ok(digest);
+    }
+    debug!(
+        "Hashing {} over SFTP, no usable {} tool on the server",
+        remote_path,
+        algorithm.name()
+    );
+    // A large buffer lets libssh2 keep several read requests in flight
+    let file = sftp
+        .open(remote_path)
+        .with_context(|| format!("Failed to open remote file: {}", remote_path))?;
+    match len {
+        Some(len) => hash_reader(algorithm, file.take(len), buffer_size),
+        None => hash_reader(algorithm, file, buffer_size),
+    }
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::debug;
+
+/// Local files and directories removed when flash is interrupted.
+static REGISTERED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+static INSTALL: Once = Once::new();
+
+/// How often the interrupt flag set by the signal handler is checked.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates a new, empty directory under the system temp directory that no
+/// other flash run uses, and registers it for removal on interrupt.
+pub fn unique_temp_dir() -> Result<PathBuf> {
+    let base = env::temp_dir();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    for attempt in 0..100u32 {
+        let dir = base.join(format!("flash-{}-{}-{}", process::id(), nanos, attempt));
+        match fs::create_dir(&dir) {
+            Ok(()) => {
+                register(&dir);
+                return Ok(dir);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Failed to create a temporary directory in {}",
+        base.display()
+    ))
+}
+
+/// Removes `path` if flash is interrupted before [`remove`] or [`unregister`]
+/// is called for it.
+pub fn register(path: &Path) {
+    REGISTERED.lock().unwrap().push(path.to_path_buf());
+}
+
+pub fn unregister(path: &Path) {
+    REGISTERED
+        .lock()
+        .unwrap()
+        .retain(|registered| registered != path);
+}
+
+/// Removes the file or directory `path` and unregisters it.
+pub fn remove(path: &Path) -> Result<()> {
+    unregister(path);
+    remove_path(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Makes Ctrl-C (and `SIGTERM`) remove the registered paths before exiting.
+/// Does nothing on platforms without Unix signals.
+pub fn install_interrupt_handler() {
+    INSTALL.call_once(|| {
+        if !install_signal_handlers() {
+            return;
+        }
+        // The signal handler only sets a flag; the files are removed here,
+        // outside of the signal context
+        thread::spawn(|| loop {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                for path in REGISTERED.lock().unwrap().drain(..) {
+                    debug!("Removing {} after interrupt", path.display());
+                    let _ = remove_path(&path);
+                }
+                eprintln!("\nInterrupted");
+                process::exit(130);
+            }
+            thread::sleep(WATCH_INTERVAL);
+        });
+    });
+}
+
+#[cfg(unix)]
+fn install_signal_handlers() -> bool {
+    extern "C" fn on_signal(_signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler) != libc::SIG_ERR
+            && libc::signal(libc::SIGTERM, handler) != libc::SIG_ERR
+    }
+}
+
+#[cfg(not(unix))]
+fn install_signal_handlers() -> bool {
+    false
+}
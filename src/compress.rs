@@ -1,15 +1,18 @@
 use anyhow::{Context, Result};
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use walkdir::WalkDir;
 use zip::write::SimpleFileOptions;
 use zip::CompressionMethod;
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum CompressionFormat {
+    #[default]
     #[clap(name = "zip")]
     Zip,
     #[clap(name = "tar")]
@@ -19,6 +22,15 @@ pub enum CompressionFormat {
 }
 
 impl CompressionFormat {
+    /// Name used on the command line and in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionFormat::Zip => "zip",
+            CompressionFormat::Tar => "tar",
+            CompressionFormat::TarGz => "tar-gz",
+        }
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             CompressionFormat::Zip => "zip",
@@ -27,6 +39,20 @@ impl CompressionFormat {
         }
     }
 
+    /// Format of an archive, judged by the extension of its file name.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(CompressionFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(CompressionFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Some(CompressionFormat::Zip)
+        } else {
+            None
+        }
+    }
+
     pub fn description(&self) -> &'static str {
         match self {
             CompressionFormat::Zip => "ZIP archive (compressed)",
@@ -36,12 +62,6 @@ impl CompressionFormat {
     }
 }
 
-impl Default for CompressionFormat {
-    fn default() -> Self {
-        CompressionFormat::Zip
-    }
-}
-
 impl FromStr for CompressionFormat {
     type Err = String;
 
@@ -65,10 +85,7 @@ pub fn compress_file_to_zip(input_path: &str, output_path: &str) -> io::Result<(
     f.read_to_end(&mut buffer)?;
 
     let file_name = path.file_name().unwrap().to_string_lossy();
-    zip.start_file(
-        file_name,
-        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
-    )?;
+    zip.start_file(file_name, zip_options(path)?)?;
     zip.write_all(&buffer)?;
     zip.finish()?;
 
@@ -167,20 +184,114 @@ pub fn compress_folder_to_zip(folder_path: &str, output_zip: &str) -> io::Result
             let mut buffer = Vec::new();
             f.read_to_end(&mut buffer)?;
 
-            zip.start_file(
-                name.to_string_lossy(),
-                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
-            )?;
+            zip.start_file(name.to_string_lossy(), zip_options(path)?)?;
             zip.write_all(&buffer)?;
-        } else if path.is_dir() {
+        } else if path.is_dir() && !name.as_os_str().is_empty() {
             let dir_name = format!("{}/", name.to_string_lossy());
-            zip.add_directory(
-                dir_name,
-                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
-            )?;
+            zip.add_directory(dir_name, zip_options(path)?)?;
         }
     }
     zip.finish()?;
 
     Ok(())
 }
+
+/// Compresses several files and directories into one archive, storing each
+/// input under the matching entry of `names`, directories with their contents
+/// below it.
+pub fn compress_bundle(
+    inputs: &[PathBuf],
+    names: &[String],
+    output_path: &str,
+    format: CompressionFormat,
+) -> Result<()> {
+    for input in inputs {
+        if !input.exists() {
+            return Err(anyhow::anyhow!(
+                "Input path does not exist: {}",
+                input.display()
+            ));
+        }
+    }
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path))?;
+
+    match format {
+        CompressionFormat::Zip => {
+            bundle_to_zip(file, inputs, names)
+                .with_context(|| "Failed to compress files to ZIP")?;
+        }
+        CompressionFormat::Tar => {
+            let mut builder = tar::Builder::new(file);
+            append_bundle(&mut builder, inputs, names)?;
+            builder
+                .finish()
+                .with_context(|| "Failed to finish TAR archive")?;
+        }
+        CompressionFormat::TarGz => {
+            let gz_encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            let mut builder = tar::Builder::new(gz_encoder);
+            append_bundle(&mut builder, inputs, names)?;
+            builder
+                .into_inner()
+                .and_then(|encoder| encoder.finish())
+                .with_context(|| "Failed to finalize TAR.GZ archive")?;
+        }
+    }
+    Ok(())
+}
+
+fn append_bundle<W: Write>(
+    builder: &mut tar::Builder<W>,
+    inputs: &[PathBuf],
+    names: &[String],
+) -> Result<()> {
+    for (input, name) in inputs.iter().zip(names) {
+        let appended = if input.is_dir() {
+            builder.append_dir_all(name, input)
+        } else {
+            builder.append_path_with_name(input, name)
+        };
+        appended.with_context(|| format!("Failed to add {} to TAR", input.display()))?;
+    }
+    Ok(())
+}
+
+fn bundle_to_zip(file: File, inputs: &[PathBuf], names: &[String]) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(file);
+    for (input, name) in inputs.iter().zip(names) {
+        for entry in WalkDir::new(input).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let relative = path.strip_prefix(input).unwrap();
+            let entry_name = if relative.as_os_str().is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", name, relative.to_string_lossy().replace('\\', "/"))
+            };
+
+            if path.is_file() {
+                let mut f = File::open(path)?;
+                zip.start_file(entry_name, zip_options(path)?)?;
+                io::copy(&mut f, &mut zip)?;
+            } else if path.is_dir() {
+                zip.add_directory(format!("{}/", entry_name), zip_options(path)?)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Deflate options carrying the Unix permissions of `path`, so extraction can
+/// restore them.
+fn zip_options(path: &Path) -> io::Result<SimpleFileOptions> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    #[cfg(unix)]
+    let options = {
+        use std::os::unix::fs::PermissionsExt;
+        options.unix_permissions(path.metadata()?.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(options)
+}
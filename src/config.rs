@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::fs;
 use std::{collections::HashMap, path::PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::checksum::ChecksumAlgorithm;
+use crate::compress::CompressionFormat;
+use crate::remote;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub name: String,
     pub ip: String,
@@ -11,11 +16,131 @@ pub struct ServerConfig {
     pub password: Option<String>,
     pub port: Option<u16>,
     pub remote_path: Option<String>,
+    /// Compression format used when none is given on the command line.
+    pub format: Option<CompressionFormat>,
+    /// Keep the replaced remote file as `<name>.prev` on upload.
+    pub keep_previous: Option<bool>,
+    /// Mode of remote directories created on upload, e.g. `0o755`.
+    pub dir_mode: Option<u32>,
+    /// Mode of the uploaded file, e.g. `0o644`.
+    pub file_mode: Option<u32>,
+    /// Numeric user id to chown the uploaded file to.
+    pub uid: Option<u32>,
+    /// Numeric group id to chgrp the uploaded file to.
+    pub gid: Option<u32>,
+    /// Commands run locally before the input is compressed.
+    pub pre_compress: Option<Vec<String>>,
+    /// Commands run locally after compression, before the upload starts.
+    pub pre_upload: Option<Vec<String>>,
+    /// Commands run on the server after a successful upload.
+    pub post_upload: Option<Vec<String>>,
+    /// Number of releases `flash deploy` keeps under `remote_path/releases`.
+    pub keep_releases: Option<usize>,
+    /// Commands run on the server after `flash deploy` switched `current`.
+    pub post_deploy: Option<Vec<String>>,
+    /// Cleanup of old archives in `remote_path`, applied after each upload.
+    pub retention: Option<RetentionPolicy>,
+    /// Command run on the server after a deploy; non-zero means unhealthy.
+    pub health_check: Option<String>,
+    /// `http://` URL probed after a deploy, `{host}` is replaced by the ip.
+    pub health_url: Option<String>,
+    /// How long a deployed host may take to become healthy, e.g. `"30s"`.
+    pub health_timeout: Option<String>,
+    /// Upload bandwidth limit in bytes per second, e.g. `"5M"`.
+    pub bandwidth_limit: Option<String>,
+    /// Hash uploads are verified with: `sha256` (default), `blake3` or `xxh3`.
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Template for the uploaded file's name, e.g. `"{name}-{date:%Y%m%d}"`.
+    pub remote_name: Option<String>,
+}
+
+/// Which uploaded archives to keep in a server's `remote_path`. An archive is
+/// removed as soon as any of the configured limits is exceeded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Number of most recent archives to keep.
+    pub keep_last: Option<usize>,
+    /// Maximum archive age, e.g. `"30d"`, `"12h"` or `"2w"`.
+    pub max_age: Option<String>,
+    /// Maximum combined size of the kept archives, e.g. `"50G"`.
+    pub max_total_size: Option<String>,
+}
+
+/// A named set of servers, selectable with `--server <group>`.
+///
+/// The optional fields are defaults for the member servers; a value set on
+/// the server itself takes precedence.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupConfig {
+    pub servers: Vec<String>,
+    pub remote_path: Option<String>,
+    pub format: Option<CompressionFormat>,
+    pub pre_compress: Option<Vec<String>>,
+    pub pre_upload: Option<Vec<String>>,
+    pub post_upload: Option<Vec<String>>,
+    pub post_deploy: Option<Vec<String>>,
+    pub health_check: Option<String>,
+    pub health_url: Option<String>,
+    pub health_timeout: Option<String>,
+    pub bandwidth_limit: Option<String>,
+    pub remote_name: Option<String>,
+}
+
+impl ServerConfig {
+    /// Fills in the settings this server leaves unset from `group`.
+    pub fn inherit(&mut self, group: &GroupConfig) {
+        fn fill<T: Clone>(own: &mut Option<T>, default: &Option<T>) {
+            if own.is_none() {
+                own.clone_from(default);
+            }
+        }
+        fill(&mut self.remote_path, &group.remote_path);
+        fill(&mut self.format, &group.format);
+        fill(&mut self.pre_compress, &group.pre_compress);
+        fill(&mut self.pre_upload, &group.pre_upload);
+        fill(&mut self.post_upload, &group.post_upload);
+        fill(&mut self.post_deploy, &group.post_deploy);
+        fill(&mut self.health_check, &group.health_check);
+        fill(&mut self.health_url, &group.health_url);
+        fill(&mut self.health_timeout, &group.health_timeout);
+        fill(&mut self.bandwidth_limit, &group.bandwidth_limit);
+        fill(&mut self.remote_name, &group.remote_name);
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(22)
+    }
+
+    /// Directory the retention policy prunes: only an explicitly configured
+    /// `remote_path`, never the home directory it defaults to.
+    pub fn retention_dir(&self) -> Result<&str> {
+        self.remote_path.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Server '{}' needs an explicit remote_path for its retention policy",
+                self.name
+            )
+        })
+    }
+
+    /// Template naming uploads and the archives retention prunes: `cli` (the
+    /// `--remote-name` option) if given, else the configured `remote_name`.
+    pub fn remote_name_template<'a>(&'a self, cli: Option<&'a str>) -> Option<&'a str> {
+        cli.or(self.remote_name.as_deref())
+    }
+
+    /// Remote directory uploads go to, `/home/<username>` unless configured.
+    pub fn remote_dir(&self) -> String {
+        self.remote_path
+            .clone()
+            .unwrap_or_else(|| format!("/home/{}", self.username))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub servers: HashMap<String, ServerConfig>,
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
     pub default: Option<DefaultConfig>,
 }
 
@@ -24,30 +149,8 @@ pub struct DefaultConfig {
     pub server: Option<String>,
 }
 
-impl Config {
-    pub fn load() -> Result<Option<Self>> {
-        let config_path = Self::get_config_path()?;
-
-        if !config_path.exists() {
-            return Ok(None);
-        }
-
-        let content = fs::read_to_string(&config_path)
-            .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
-        let config: Config =
-            toml::from_str(&content).with_context(|| "Failed to parse config file")?;
-
-        Ok(Some(config))
-    }
-
-    pub fn create_example() -> Result<()> {
-        let config_path = Self::get_config_path()?;
-        if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("Failed to create config directory: {:?}", parent))?;
-        }
-
-        let example_config = r#"# Flash configuration file
+/// Contents written by `flash config init`.
+pub const EXAMPLE_CONFIG: &str = r#"# Flash configuration file
 # You can define multiple servers and choose between them
 
 [servers.home]
@@ -57,6 +160,7 @@ username = "admin"
 password = "123"
 port = 22
 remote_path = "/home/admin"
+keep_previous = true  # Keep the replaced file as <name>.prev
 
 [servers.work]
 name = "Work Server"
@@ -64,19 +168,93 @@ ip = "10.0.0.5"
 username = "deploy"
 port = 2222
 remote_path = "/opt/uploads"
+bandwidth_limit = "5M"  # Upload at most 5 MiB/s to this server
+remote_name = "{name}-{date:%Y%m%d}-{git_sha}"  # Name of the uploaded file
+dir_mode = 0o775   # Mode of created remote directories
+file_mode = 0o640  # Mode of the uploaded file
+# uid = 1001       # Optional numeric owner of the uploaded file
+# gid = 1001       # Optional numeric group of the uploaded file
+
+# Local commands run before compressing and before uploading. They receive
+# FLASH_SOURCE, FLASH_ARCHIVE, FLASH_FORMAT, FLASH_SERVER, FLASH_HOST and
+# FLASH_REMOTE_PATH in their environment.
+# pre_compress = ["npm run build"]
+# pre_upload = ["./scripts/sign.sh \"$FLASH_ARCHIVE\""]
+
+# Commands run on the server after upload. Placeholders: {remote_file},
+# {remote_dir}, {archive}, {format}
+# post_upload = ["tar -xzf {remote_file} -C /srv/app", "systemctl restart app"]
+
+# `flash deploy` keeps this many releases and runs these commands after the
+# `current` symlink was switched. Extra placeholders: {release}, {release_dir}
+keep_releases = 5
+# post_deploy = ["systemctl restart app"]
+
+# Remove old archives from remote_path after each upload (see `flash prune`)
+[servers.work.retention]
+keep_last = 10
+max_age = "30d"
+max_total_size = "50G"
+
+# Groups are selected like servers (`--server all`) and provide defaults for
+# remote_path, format and hooks that their members may override.
+[groups.all]
+servers = ["home", "work"]
+format = "tar-gz"
+# `flash deploy --server all --batch-size 1` waits for each host to pass these
+# checks before moving on, and rolls the deployed hosts back if one fails.
+# health_check = "systemctl is-active app"
+# health_url = "http://{host}:8080/health"
+# health_timeout = "30s"
 
 [default]
 server = "home"  # Default server to use
 "#;
 
-        fs::write(&config_path, example_config).with_context(|| {
+impl Config {
+    pub fn load() -> Result<Option<Self>> {
+        let config_path = Self::path()?;
+
+        if !config_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+        let config = Self::from_toml(&content, &|name| env::var(name).ok())?;
+
+        Ok(Some(config))
+    }
+
+    /// Parses a configuration file, interpolating `${VAR}` in its strings
+    /// with the values `lookup` returns (see [`interpolate_env`]). Hook and
+    /// health check commands keep references to unset variables.
+    pub fn from_toml(content: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut value: toml::Value =
+            toml::from_str(content).with_context(|| "Failed to parse config file")?;
+        interpolate_value(&mut value, "", false, lookup)?;
+        value
+            .try_into()
+            .with_context(|| "Failed to parse config file")
+    }
+
+    pub fn create_example() -> Result<()> {
+        let config_path = Self::path()?;
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create config directory: {:?}", parent))?;
+        }
+
+        fs::write(&config_path, EXAMPLE_CONFIG).with_context(|| {
             format!("Failed to write example config file to: {:?}", config_path)
         })?;
         println!("Example configuration file created at: {:?}", config_path);
         Ok(())
     }
 
-    fn get_config_path() -> Result<PathBuf> {
+    /// Path of the configuration file: `flash.toml` in the current directory
+    /// if it exists, the user's config directory otherwise.
+    pub fn path() -> Result<PathBuf> {
         let local_config = PathBuf::from("flash.toml");
         if local_config.exists() {
             return Ok(local_config);
@@ -98,6 +276,202 @@ server = "home"  # Default server to use
     }
 
     pub fn list_servers(&self) -> Vec<(&String, &ServerConfig)> {
-        self.servers.iter().collect()
+        let mut servers: Vec<_> = self.servers.iter().collect();
+        servers.sort_by_key(|(key, _)| *key);
+        servers
+    }
+
+    pub fn get_group(&self, name: &str) -> Option<&GroupConfig> {
+        self.groups.get(name)
+    }
+
+    pub fn list_groups(&self) -> Vec<(&String, &GroupConfig)> {
+        let mut groups: Vec<_> = self.groups.iter().collect();
+        groups.sort_by_key(|(key, _)| *key);
+        groups
+    }
+
+    /// Checks that the default server and every group member exist.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(name) = self.default.as_ref().and_then(|d| d.server.as_ref()) {
+            if self.get_server(name).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Default server '{}' not found in config.",
+                    name
+                ));
+            }
+        }
+        for (name, group) in self.list_groups() {
+            self.group_members(name, group)?;
+        }
+        Ok(())
+    }
+
+    /// Servers that `name` refers to: the server of that name, or else every
+    /// member of the group of that name with the group's defaults applied.
+    pub fn resolve(&self, name: &str) -> Result<Vec<ServerConfig>> {
+        if let Some(server) = self.get_server(name) {
+            return Ok(vec![server.clone()]);
+        }
+        let group = self
+            .get_group(name)
+            .ok_or_else(|| anyhow::anyhow!("Server '{}' not found in config.", name))?;
+        self.group_members(name, group)
+    }
+
+    /// Members of `group` with the group's defaults applied. A group without
+    /// members is an error.
+    pub fn group_members(&self, name: &str, group: &GroupConfig) -> Result<Vec<ServerConfig>> {
+        if group.servers.is_empty() {
+            return Err(anyhow::anyhow!("Group '{}' has no servers.", name));
+        }
+        group
+            .servers
+            .iter()
+            .map(|member| {
+                let mut server = self.get_server(member).cloned().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Server '{}' of group '{}' not found in config.",
+                        member,
+                        name
+                    )
+                })?;
+                server.inherit(group);
+                Ok(server)
+            })
+            .collect()
+    }
+}
+
+/// Replaces `${VAR}` in `value` with what `lookup` returns for `VAR`, and
+/// `${VAR:-default}` with `default` when it returns nothing or an empty
+/// string. `$${` stands for a literal `${`.
+pub fn interpolate_env(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    interpolate(value, lookup, false)
+}
+
+/// Like [`interpolate_env`], for commands that are run later by a shell:
+/// references to unset variables and shell expansions such as `${#VAR}` are
+/// left for the shell, e.g. `${FLASH_ARCHIVE}` in a hook.
+pub fn interpolate_command(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    interpolate(value, lookup, true).unwrap_or_else(|_| value.to_string())
+}
+
+fn interpolate(
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    keep_unknown: bool,
+) -> Result<String> {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        let tail = &rest[index..];
+        if let Some(after) = tail.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                if keep_unknown {
+                    output.push_str(tail);
+                    return Ok(output);
+                }
+                return Err(anyhow::anyhow!("Unclosed '${{' in \"{}\"", value));
+            };
+            let reference = &tail[..end + 3];
+            rest = &after[end + 1..];
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            if !is_variable_name(name) {
+                if keep_unknown {
+                    output.push_str(reference);
+                    continue;
+                }
+                return Err(anyhow::anyhow!(
+                    "Invalid variable name '{}' in \"{}\"",
+                    name,
+                    value
+                ));
+            }
+            let resolved = match (lookup(name), default) {
+                (Some(resolved), Some(default)) if resolved.is_empty() => default.to_string(),
+                (Some(resolved), _) => resolved,
+                (None, Some(default)) => default.to_string(),
+                (None, None) if keep_unknown => reference.to_string(),
+                (None, None) => {
+                    return Err(anyhow::anyhow!("Environment variable {} is not set", name))
+                }
+            };
+            output.push_str(&resolved);
+        } else {
+            output.push('$');
+            rest = &tail[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Settings holding commands for a shell, see [`interpolate_command`].
+const COMMAND_FIELDS: &[&str] = &[
+    "pre_compress",
+    "pre_upload",
+    "post_upload",
+    "post_deploy",
+    "health_check",
+];
+
+/// Interpolates every string in `value`, naming the offending key on errors.
+/// Strings below a command setting are interpolated with
+/// [`interpolate_command`], and a leading `${HOME}` in a `remote_path` is left
+/// for the server to resolve.
+fn interpolate_value(
+    value: &mut toml::Value,
+    key: &str,
+    command: bool,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<()> {
+    match value {
+        toml::Value::String(string) if command => {
+            *string = interpolate_command(string, lookup);
+        }
+        // The home directory of a remote path is the one on the server
+        toml::Value::String(string) if key.ends_with(".remote_path") => {
+            let rest = remote::home_relative(string).unwrap_or(string);
+            let home = &string[..string.len() - rest.len()];
+            let rest = interpolate_env(rest, lookup).with_context(|| format!("In {}", key))?;
+            *string = format!("{}{}", home, rest);
+        }
+        toml::Value::String(string) => {
+            *string = interpolate_env(string, lookup).with_context(|| format!("In {}", key))?;
+        }
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", key, index), command, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, item) in table.iter_mut() {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                let command = command || COMMAND_FIELDS.contains(&name.as_str());
+                interpolate_value(item, &key, command, lookup)?;
+            }
+        }
+        _ => {}
     }
+    Ok(())
 }
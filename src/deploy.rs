@@ -0,0 +1,280 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::warn;
+use ssh2::Session;
+
+use crate::compress::CompressionFormat;
+use crate::remote::{self, shell_quote};
+use crate::upload::create_remote_dir_all;
+
+/// Directory under the server's `remote_path` holding one directory per release.
+pub const RELEASES_DIR: &str = "releases";
+
+/// Symlink under the server's `remote_path` pointing at the live release.
+pub const CURRENT_LINK: &str = "current";
+
+/// Number of releases kept on the server when `keep_releases` is not configured.
+pub const DEFAULT_KEEP_RELEASES: usize = 5;
+
+/// Capistrano-style layout rooted at a server's `remote_path`:
+///
+/// ```text
+/// <root>/releases/20261019120000/
+/// <root>/releases/20261019133000/
+/// <root>/current -> releases/20261019133000
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReleaseLayout {
+    pub root: String,
+}
+
+impl ReleaseLayout {
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: root.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn releases_dir(&self) -> String {
+        format!("{}/{}", self.root, RELEASES_DIR)
+    }
+
+    pub fn release_dir(&self, release: &str) -> String {
+        format!("{}/{}", self.releases_dir(), release)
+    }
+
+    /// Remote path the archive of `release` is uploaded to before extraction.
+    pub fn release_archive(&self, release: &str, format: CompressionFormat) -> String {
+        format!("{}.{}", self.release_dir(release), format.extension())
+    }
+
+    pub fn current_link(&self) -> String {
+        format!("{}/{}", self.root, CURRENT_LINK)
+    }
+}
+
+/// Name of a new release, the local timestamp as `YYYYMMDDHHMMSS`.
+///
+/// Release names sort chronologically, which the rest of this module relies on.
+pub fn release_name() -> String {
+    Local::now().format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Most releases [`reserve_release`] tries when the name is taken.
+const MAX_RELEASE_SUFFIX: usize = 99;
+
+/// Names tried for a release called `release`: the name itself, then with the
+/// suffixes `-02`, `-03` and so on, which still sort after `release` and
+/// before any later timestamp.
+pub fn release_candidates(release: &str) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(release.to_string())
+        .chain((2..=MAX_RELEASE_SUFFIX).map(move |suffix| format!("{}-{:02}", release, suffix)))
+}
+
+/// Creates the directory of a new release and returns its name: `release`,
+/// or a suffixed name when a deploy in the same second already took it.
+pub fn reserve_release(session: &Session, layout: &ReleaseLayout, release: &str) -> Result<String> {
+    let sftp = session.sftp()?;
+    create_remote_dir_all(&sftp, Path::new(&layout.releases_dir()), 0o755)?;
+    for candidate in release_candidates(release) {
+        let release_dir = layout.release_dir(&candidate);
+        // mkdir fails on an existing directory, so concurrent deploys never share one
+        match sftp.mkdir(Path::new(&release_dir), 0o755) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if sftp.lstat(Path::new(&release_dir)).is_err() => {
+                return Err(e)
+                    .with_context(|| format!("Failed to create release directory {}", release_dir))
+            }
+            Err(_) => {}
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Too many releases named {} in {}",
+        release,
+        layout.releases_dir()
+    ))
+}
+
+/// Extracts the uploaded `archive` into a new release directory, removes the
+/// archive and points `current` at the new release. Returns the name of the
+/// release, see [`reserve_release`]. The release directory is removed again
+/// when any step after reserving it fails.
+pub fn activate_release(
+    session: &Session,
+    layout: &ReleaseLayout,
+    release: &str,
+    archive: &str,
+    format: CompressionFormat,
+) -> Result<String> {
+    let release = reserve_release(session, layout, release)?;
+    let installed = install_release(session, layout, &release, archive, format);
+    discard_on_error(installed, || remove_release(session, layout, &release))?;
+    Ok(release)
+}
+
+fn install_release(
+    session: &Session,
+    layout: &ReleaseLayout,
+    release: &str,
+    archive: &str,
+    format: CompressionFormat,
+) -> Result<()> {
+    remote::extract_archive(session, archive, format, &layout.release_dir(release))?;
+    session
+        .sftp()?
+        .unlink(Path::new(archive))
+        .with_context(|| format!("Failed to remove release archive: {}", archive))?;
+    switch_current(session, layout, release)
+}
+
+/// Runs `discard` when `result` is an error and returns `result` unchanged.
+/// A failing `discard` is only logged, the original error is what matters.
+pub fn discard_on_error<T>(result: Result<T>, discard: impl FnOnce() -> Result<()>) -> Result<T> {
+    if result.is_err() {
+        if let Err(e) = discard() {
+            warn!("{:#}", e);
+        }
+    }
+    result
+}
+
+/// Shell command that atomically renames the symlink `$1` over `$2`.
+///
+/// `mv -T` is GNU only; on BSD, macOS and busybox hosts the rename(2) is done
+/// with python or perl instead. A plain `mv` would move the link into the
+/// directory `current` points at.
+const RENAME_LINK: &str = r#"mv -Tf "$1" "$2" 2>/dev/null ||
+python3 -c 'import os, sys; os.rename(sys.argv[1], sys.argv[2])' "$1" "$2" 2>/dev/null ||
+python -c 'import os, sys; os.rename(sys.argv[1], sys.argv[2])' "$1" "$2" 2>/dev/null ||
+perl -e 'rename($ARGV[0], $ARGV[1]) or die "$!\n"' "$1" "$2" 2>/dev/null ||
+{ rm -f "$1"; echo "cannot replace $2 atomically: needs GNU mv, python or perl" >&2; exit 1; }"#;
+
+/// Atomically repoints the `current` symlink at `release`.
+///
+/// The new link is created under a temporary name and renamed over `current`
+/// (see [`RENAME_LINK`]), so `current` always resolves to a complete release.
+pub fn switch_current(session: &Session, layout: &ReleaseLayout, release: &str) -> Result<()> {
+    let temp_link = format!("{}.flash-tmp", layout.current_link());
+    let command = format!(
+        "ln -sfn {} {} && sh -c {} sh {} {}",
+        shell_quote(&format!("{}/{}", RELEASES_DIR, release)),
+        shell_quote(&temp_link),
+        shell_quote(RENAME_LINK),
+        shell_quote(&temp_link),
+        shell_quote(&layout.current_link())
+    );
+    let output = remote::exec_capture(session, &command)?;
+    if !output.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to switch {} to release {}: {}",
+            layout.current_link(),
+            release,
+            output.stderr.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Lists the release directories on the server, oldest first.
+pub fn list_releases(session: &Session, layout: &ReleaseLayout) -> Result<Vec<String>> {
+    let releases_dir = layout.releases_dir();
+    let entries = session
+        .sftp()?
+        .readdir(Path::new(&releases_dir))
+        .with_context(|| format!("Failed to list releases in {}", releases_dir))?;
+    let mut releases: Vec<String> = entries
+        .into_iter()
+        .filter(|(_, stat)| stat.is_dir())
+        .filter_map(|(path, _)| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+    releases.sort();
+    Ok(releases)
+}
+
+/// Returns the release `current` points at, if the link exists.
+pub fn current_release(session: &Session, layout: &ReleaseLayout) -> Result<Option<String>> {
+    match session.sftp()?.readlink(Path::new(&layout.current_link())) {
+        Ok(target) => Ok(target
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Picks the release to roll back to: `to` if given, otherwise the release
+/// deployed right before `current`.
+///
+/// `releases` must be sorted oldest first.
+pub fn rollback_target(
+    releases: &[String],
+    current: Option<&str>,
+    to: Option<&str>,
+) -> Result<String> {
+    if let Some(to) = to {
+        return if releases.iter().any(|release| release == to) {
+            Ok(to.to_string())
+        } else {
+            Err(anyhow::anyhow!("Release '{}' not found on the server", to))
+        };
+    }
+
+    let current =
+        current.ok_or_else(|| anyhow::anyhow!("No current release, nothing to roll back"))?;
+    let index = releases
+        .iter()
+        .position(|release| release == current)
+        .ok_or_else(|| anyhow::anyhow!("Current release '{}' not found on the server", current))?;
+    if index == 0 {
+        return Err(anyhow::anyhow!(
+            "No release older than '{}' to roll back to",
+            current
+        ));
+    }
+    Ok(releases[index - 1].clone())
+}
+
+/// Picks the releases to delete so that only the newest `keep` remain.
+///
+/// `releases` must be sorted oldest first. The current release is never
+/// selected, even when it falls outside the newest `keep`.
+pub fn releases_to_remove(releases: &[String], current: Option<&str>, keep: usize) -> Vec<String> {
+    let excess = releases.len().saturating_sub(keep);
+    releases[..excess]
+        .iter()
+        .filter(|release| Some(release.as_str()) != current)
+        .cloned()
+        .collect()
+}
+
+/// Deletes all but the newest `keep` releases and returns the removed ones.
+pub fn remove_old_releases(
+    session: &Session,
+    layout: &ReleaseLayout,
+    keep: usize,
+) -> Result<Vec<String>> {
+    let releases = list_releases(session, layout)?;
+    let current = current_release(session, layout)?;
+    let stale = releases_to_remove(&releases, current.as_deref(), keep);
+    for release in &stale {
+        remove_release(session, layout, release)?;
+    }
+    Ok(stale)
+}
+
+/// Deletes the directory of `release`.
+fn remove_release(session: &Session, layout: &ReleaseLayout, release: &str) -> Result<()> {
+    let output = remote::exec_capture(
+        session,
+        &format!("rm -rf {}", shell_quote(&layout.release_dir(release))),
+    )?;
+    if !output.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to remove release {}: {}",
+            release,
+            output.stderr.trim()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use ssh2::{Session, Sftp};
+
+use crate::checksum::{self, ChecksumAlgorithm, HashingWriter};
+use crate::compress::CompressionFormat;
+use crate::loading::TransferProgress;
+use crate::remote::{self, shell_quote};
+use crate::upload::{self, SshTarget, DEFAULT_BUFFER_SIZE, PARTIAL_SUFFIX};
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Size of the read and write buffers.
+    pub buffer_size: usize,
+    /// Hash the download is verified with.
+    pub checksum: ChecksumAlgorithm,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            checksum: ChecksumAlgorithm::default(),
+        }
+    }
+}
+
+/// Local file a download is written to before being renamed into place, and
+/// resumed from after an interruption.
+pub fn partial_path(local_path: &Path) -> PathBuf {
+    let mut name = local_path.as_os_str().to_owned();
+    name.push(PARTIAL_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Downloads `remote_path` to `local_path` over an established session.
+///
+/// Data is written to `<local_path>.flash-partial`, continuing where an earlier
+/// attempt left off, verified against the remote checksum and only then
+/// renamed to `local_path`. A file that grows meanwhile, such as a live log, is
+/// downloaded and verified up to the size it had when the download started.
+/// `progress` is left running.
+pub fn download_file(
+    session: &Session,
+    remote_path: &str,
+    local_path: &Path,
+    options: &DownloadOptions,
+    progress: &TransferProgress,
+) -> Result<()> {
+    progress.spin("Checking remote file...");
+    let sftp = session.sftp()?;
+    let remote_size = sftp
+        .stat(Path::new(remote_path))
+        .with_context(|| format!("Remote file not found: {}", remote_path))?
+        .size
+        .unwrap_or(0);
+
+    let partial = partial_path(local_path);
+    let start_pos = match fs::metadata(&partial) {
+        // A partial file larger than the remote one belongs to another file
+        Ok(meta) if meta.len() <= remote_size => meta.len(),
+        _ => 0,
+    };
+    if start_pos > 0 {
+        progress.println(&format!("Resuming download from position: {}", start_pos));
+    }
+
+    let mut local_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(start_pos == 0)
+        .open(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    local_file.seek(SeekFrom::Start(start_pos))?;
+    let mut remote_file = sftp.open(Path::new(remote_path))?;
+    remote_file.seek(SeekFrom::Start(start_pos))?;
+
+    progress.start_transfer(remote_size, start_pos);
+    let mut position = start_pos;
+    // Only the size seen up front, so a log being written to can be verified
+    let remote_data = remote_file.take(remote_size - start_pos);
+    upload::pipelined_copy(remote_data, &mut local_file, options.buffer_size, |n| {
+        position += n;
+        progress.set_position(position);
+    })?;
+    local_file.flush()?;
+    drop(local_file);
+
+    progress.spin("Verifying download...");
+    let local_hash = checksum::hash_file(
+        options.checksum,
+        &partial.to_string_lossy(),
+        options.buffer_size,
+    )?;
+    let remote_hash = checksum::remote_prefix_digest(
+        session,
+        &sftp,
+        options.checksum,
+        remote_path,
+        position,
+        options.buffer_size,
+    )?;
+    if local_hash != remote_hash {
+        // A corrupt partial file must not be resumed from on the next attempt
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow::anyhow!(
+            "{} mismatch after download: remote {} != local {}",
+            options.checksum.name(),
+            remote_hash,
+            local_hash
+        ));
+    }
+
+    let grown = sftp
+        .stat(Path::new(remote_path))
+        .ok()
+        .and_then(|stat| stat.size)
+        .is_some_and(|size| size > position);
+    if grown {
+        progress.println(&format!(
+            "{} grew while downloading, saved and verified its first {}",
+            remote_path,
+            HumanBytes(position)
+        ));
+    }
+
+    fs::rename(&partial, local_path).with_context(|| {
+        format!(
+            "Failed to rename {} to {}",
+            partial.display(),
+            local_path.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// Downloads `remote_path` to `local_path`, reconnecting and resuming up to
+/// `max_retries` times. `progress` is left running.
+pub fn download_with_retry(
+    target: &SshTarget,
+    remote_path: &str,
+    local_path: &Path,
+    max_retries: u32,
+    options: &DownloadOptions,
+    progress: &TransferProgress,
+) -> Result<()> {
+    let mut last_err = None;
+    for attempt in 1..=max_retries {
+        let result = upload::open_session(target, progress).and_then(|session| {
+            download_file(&session, remote_path, local_path, options, progress)
+        });
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                progress.println(&format!("❌ Attempt {} failed: {}", attempt, e));
+                last_err = Some(e);
+                if attempt < max_retries {
+                    progress.spin("Retrying in 2 seconds...");
+                    thread::sleep(Duration::from_secs(2));
+                }
+            }
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Failed to download after {} attempts: {}",
+        max_retries,
+        last_err.unwrap()
+    ))
+}
+
+/// Temporary archive of a remote directory created by [`create_remote_archive`].
+#[derive(Debug)]
+pub struct RemoteArchive {
+    pub path: String,
+    /// Complaints of `tar` that did not stop it, e.g. about files that changed
+    /// while they were read.
+    pub warnings: Option<String>,
+}
+
+/// Splits a remote path into the directory to run `tar -C` in and the entry
+/// to archive, so the archive contains the directory under its own name.
+pub fn tar_operands(remote_dir: &str) -> (String, String) {
+    let trimmed = remote_dir.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        _ if trimmed.is_empty() => ("/".to_string(), ".".to_string()),
+        Some(("", name)) => ("/".to_string(), name.to_string()),
+        Some((parent, name)) => (parent.to_string(), name.to_string()),
+        None => (".".to_string(), trimmed.to_string()),
+    }
+}
+
+/// Archives `remote_dir` on the server with `tar` into a temporary file.
+pub fn create_remote_archive(
+    session: &Session,
+    remote_dir: &str,
+    format: CompressionFormat,
+) -> Result<RemoteArchive> {
+    let flags = match format {
+        CompressionFormat::Tar => "-cf",
+        CompressionFormat::TarGz => "-czf",
+        CompressionFormat::Zip => {
+            return Err(anyhow::anyhow!(
+                "Remote directories can only be archived as tar or tar-gz"
+            ))
+        }
+    };
+    if !remote::has_command(session, "tar")? {
+        return Err(anyhow::anyhow!("`tar` is not installed on the server"));
+    }
+
+    let output = remote::exec_capture(session, "mktemp \"${TMPDIR:-/tmp}/flash-pull.XXXXXX\"")?;
+    let path = output.stdout.trim().to_string();
+    if !output.success() || path.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Failed to create a temporary file on the server: {}",
+            output.stderr.trim()
+        ));
+    }
+
+    let (parent, name) = tar_operands(remote_dir);
+    let output = remote::exec_capture(
+        session,
+        &format!(
+            "tar {} {} -C {} {}",
+            flags,
+            shell_quote(&path),
+            shell_quote(&parent),
+            shell_quote(&name)
+        ),
+    )?;
+    // GNU tar exits with 1 when files changed while being archived, which is
+    // expected for live logs
+    match output.exit_status {
+        0 => Ok(RemoteArchive {
+            path,
+            warnings: None,
+        }),
+        1 => Ok(RemoteArchive {
+            path,
+            warnings: Some(output.stderr.trim().to_string()),
+        }),
+        status => {
+            let _ = session
+                .sftp()
+                .and_then(|sftp| sftp.unlink(Path::new(&path)));
+            Err(anyhow::anyhow!(
+                "Failed to archive {} (exit status {}): {}",
+                remote_dir,
+                status,
+                output.stderr.trim()
+            ))
+        }
+    }
+}
+
+/// Copies the remote directory `remote_dir` into `local_dir` over SFTP and
+/// returns the number of bytes copied. Symlinks and special files are skipped.
+///
+/// Each file is verified against the remote checksum of the bytes copied, so
+/// files that grow meanwhile are copied up to the size listed.
+pub fn mirror_dir(
+    session: &Session,
+    sftp: &Sftp,
+    remote_dir: &Path,
+    local_dir: &Path,
+    options: &DownloadOptions,
+    progress: &TransferProgress,
+) -> Result<u64> {
+    fs::create_dir_all(local_dir)
+        .with_context(|| format!("Failed to create {}", local_dir.display()))?;
+    let entries = sftp
+        .readdir(remote_dir)
+        .with_context(|| format!("Failed to list {}", remote_dir.display()))?;
+
+    let mut copied = 0;
+    for (remote_entry, stat) in entries {
+        let Some(name) = remote_entry.file_name() else {
+            continue;
+        };
+        let local_entry = local_dir.join(name);
+        if stat.is_dir() {
+            copied += mirror_dir(
+                session,
+                sftp,
+                &remote_entry,
+                &local_entry,
+                options,
+                progress,
+            )?;
+        } else if stat.is_file() {
+            progress.set_message(&format!("Copying {}...", remote_entry.display()));
+            copied += mirror_file(
+                session,
+                sftp,
+                &remote_entry,
+                stat.size.unwrap_or(0),
+                &local_entry,
+                options,
+            )?;
+        }
+    }
+    progress.set_message(&format!("Copied {}", HumanBytes(copied)));
+    Ok(copied)
+}
+
+/// Copies the first `size` bytes of `remote_file` to `local_file` and
+/// verifies them against the remote checksum.
+fn mirror_file(
+    session: &Session,
+    sftp: &Sftp,
+    remote_file: &Path,
+    size: u64,
+    local_file: &Path,
+    options: &DownloadOptions,
+) -> Result<u64> {
+    let remote_data = sftp
+        .open(remote_file)
+        .with_context(|| format!("Failed to open {}", remote_file.display()))?
+        .take(size);
+    let file = fs::File::create(local_file)
+        .with_context(|| format!("Failed to create {}", local_file.display()))?;
+    let mut writer = HashingWriter::new(options.checksum, file);
+    let copied = upload::pipelined_copy(remote_data, &mut writer, options.buffer_size, |_| {})?;
+    let local_hash = writer.finish();
+
+    let remote_path = remote_file.to_string_lossy();
+    let remote_hash = checksum::remote_prefix_digest(
+        session,
+        sftp,
+        options.checksum,
+        &remote_path,
+        copied,
+        options.buffer_size,
+    )?;
+    if local_hash != remote_hash {
+        return Err(anyhow::anyhow!(
+            "{} mismatch after copying {}: remote {} != local {}",
+            options.checksum.name(),
+            remote_path,
+            remote_hash,
+            local_hash
+        ));
+    }
+    Ok(copied)
+}
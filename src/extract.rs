@@ -0,0 +1,362 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+
+use crate::compress::CompressionFormat;
+use crate::loading::TransferProgress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Hard links, devices and other special entries.
+    Other,
+}
+
+/// Entry of an archive as listed by [`list_archive`].
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    /// Unix permission bits, if the archive records them.
+    pub mode: Option<u32>,
+    pub link_target: Option<PathBuf>,
+}
+
+/// What [`extract_archive`] wrote.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExtractSummary {
+    pub files: u64,
+    pub dirs: u64,
+    /// Symlinks, hard links and other special entries.
+    pub links: u64,
+    pub bytes: u64,
+}
+
+/// Normalizes the path of an archive entry, or returns `None` if extracting it
+/// could write outside the destination directory (zip-slip): absolute paths,
+/// drive prefixes and `..` components are rejected.
+pub fn safe_entry_path(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Lists the entries of `archive` without extracting it.
+pub fn list_archive(archive: &Path, format: CompressionFormat) -> Result<Vec<ArchiveEntry>> {
+    let file = open_archive(archive)?;
+    let listed = match format {
+        CompressionFormat::Zip => list_zip(file),
+        CompressionFormat::Tar => list_tar(tar::Archive::new(file)),
+        CompressionFormat::TarGz => list_tar(tar::Archive::new(GzDecoder::new(file))),
+    };
+    listed.with_context(|| format!("Failed to read {}", archive.display()))
+}
+
+fn list_zip(file: impl Read + io::Seek) -> Result<Vec<ArchiveEntry>> {
+    let mut zip = zip::ZipArchive::new(file)?;
+    let mut entries = Vec::with_capacity(zip.len());
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        let kind = zip_kind(&entry);
+        let link_target = if kind == EntryKind::Symlink {
+            let mut target = String::new();
+            entry.read_to_string(&mut target)?;
+            Some(PathBuf::from(target))
+        } else {
+            None
+        };
+        entries.push(ArchiveEntry {
+            path: PathBuf::from(entry.name()),
+            kind,
+            size: entry.size(),
+            mode: entry.unix_mode().map(|mode| mode & 0o7777),
+            link_target,
+        });
+    }
+    Ok(entries)
+}
+
+fn list_tar(mut archive: tar::Archive<impl Read>) -> Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        entries.push(ArchiveEntry {
+            path: entry.path()?.into_owned(),
+            kind: tar_kind(header.entry_type()),
+            size: header.size()?,
+            mode: header.mode().ok(),
+            link_target: entry.link_name()?.map(|target| target.into_owned()),
+        });
+    }
+    Ok(entries)
+}
+
+pub(crate) fn zip_kind(entry: &zip::read::ZipFile<'_, impl Read>) -> EntryKind {
+    if entry.is_dir() {
+        EntryKind::Dir
+    } else if entry.is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    }
+}
+
+pub(crate) fn tar_kind(entry_type: tar::EntryType) -> EntryKind {
+    match entry_type {
+        tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+            EntryKind::File
+        }
+        tar::EntryType::Directory => EntryKind::Dir,
+        tar::EntryType::Symlink => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    }
+}
+
+/// Extracts `archive` into `dest_dir`, creating it if needed.
+///
+/// Unix permissions recorded in the archive are restored. Entries whose path
+/// would leave `dest_dir` abort the extraction. `progress` follows the bytes
+/// read from the archive and is left running.
+pub fn extract_archive(
+    archive: &Path,
+    format: CompressionFormat,
+    dest_dir: &Path,
+    progress: &TransferProgress,
+) -> Result<ExtractSummary> {
+    fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Failed to create {}", dest_dir.display()))?;
+    let size = fs::metadata(archive)
+        .with_context(|| format!("Failed to open {}", archive.display()))?
+        .len();
+    let file = CountingReader::new(open_archive(archive)?, size, progress);
+
+    progress.start_transfer(size, 0);
+    let extracted = match format {
+        CompressionFormat::Zip => extract_zip(file, dest_dir),
+        CompressionFormat::Tar => extract_tar(tar::Archive::new(file), dest_dir),
+        CompressionFormat::TarGz => extract_tar(tar::Archive::new(GzDecoder::new(file)), dest_dir),
+    };
+    extracted.with_context(|| {
+        format!(
+            "Failed to extract {} into {}",
+            archive.display(),
+            dest_dir.display()
+        )
+    })
+}
+
+pub(crate) fn open_archive(archive: &Path) -> Result<BufReader<File>> {
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    Ok(BufReader::new(file))
+}
+
+fn unsafe_path(name: &Path) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Refusing to extract {}: the path leaves the destination directory",
+        name.display()
+    )
+}
+
+/// Creates the directory `relative` below `root`, which must be canonical,
+/// along with its missing parents. Unlike `create_dir_all` it refuses to go
+/// through symlinks, e.g. ones extracted earlier, that lead outside `root`.
+fn create_dir_inside(root: &Path, relative: &Path, name: &Path) -> Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_err() {
+            fs::create_dir(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+        } else if !path
+            .canonicalize()
+            .is_ok_and(|resolved| resolved.starts_with(root))
+        {
+            return Err(unsafe_path(name));
+        }
+    }
+    Ok(path)
+}
+
+/// Applies the deferred `mode` of the extracted directory `dir` below `root`,
+/// unless a symlink has taken its place since.
+fn set_dir_mode(root: &Path, dir: &Path, mode: u32) -> Result<()> {
+    let is_dir = fs::symlink_metadata(dir).is_ok_and(|meta| meta.is_dir());
+    if !is_dir || !dir.canonicalize()?.starts_with(root) {
+        return Err(unsafe_path(dir));
+    }
+    set_mode(dir, mode)
+}
+
+fn extract_tar(mut archive: tar::Archive<impl Read>, dest_dir: &Path) -> Result<ExtractSummary> {
+    // Applies the permission bits but drops setuid, setgid and sticky bits
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_mtime(true);
+
+    let root = dest_dir.canonicalize()?;
+    let mut summary = ExtractSummary::default();
+    let mut dir_modes = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let relative = safe_entry_path(&name).ok_or_else(|| unsafe_path(&name))?;
+        let kind = tar_kind(entry.header().entry_type());
+        let size = entry.header().size()?;
+        if kind == EntryKind::Dir {
+            // Created with default permissions until all entries are written,
+            // so read-only directories can still be filled
+            let out_path = create_dir_inside(&root, &relative, &name)?;
+            if let Ok(mode) = entry.header().mode() {
+                dir_modes.push((out_path, mode & 0o777));
+            }
+            summary.dirs += 1;
+            continue;
+        }
+        // unpack_in also refuses to write through symlinks leading outside
+        if !entry.unpack_in(dest_dir)? {
+            return Err(unsafe_path(&name));
+        }
+        match kind {
+            EntryKind::File => {
+                summary.files += 1;
+                summary.bytes += size;
+            }
+            EntryKind::Dir => {}
+            EntryKind::Symlink | EntryKind::Other => summary.links += 1,
+        }
+    }
+
+    for (dir, mode) in dir_modes.iter().rev() {
+        set_dir_mode(&root, dir, *mode)?;
+    }
+    Ok(summary)
+}
+
+fn extract_zip(file: impl Read + io::Seek, dest_dir: &Path) -> Result<ExtractSummary> {
+    let mut zip = zip::ZipArchive::new(file)?;
+    let root = dest_dir.canonicalize()?;
+    let mut summary = ExtractSummary::default();
+    // Applied last, so read-only directories can still be filled
+    let mut dir_modes = Vec::new();
+
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        let name = PathBuf::from(entry.name());
+        let relative = safe_entry_path(&name).ok_or_else(|| unsafe_path(&name))?;
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let out_path = root.join(&relative);
+        // setuid, setgid and sticky bits are dropped, as by `tar` for non-root users
+        let mode = entry.unix_mode().map(|mode| mode & 0o777);
+
+        if entry.is_dir() {
+            create_dir_inside(&root, &relative, &name)?;
+            if let Some(mode) = mode {
+                dir_modes.push((out_path, mode));
+            }
+            summary.dirs += 1;
+            continue;
+        }
+
+        // A symlink extracted earlier must not redirect this entry elsewhere
+        create_dir_inside(&root, relative.parent().unwrap_or(Path::new("")), &name)?;
+        if fs::symlink_metadata(&out_path).is_ok_and(|meta| !meta.is_dir()) {
+            fs::remove_file(&out_path)
+                .with_context(|| format!("Failed to replace {}", out_path.display()))?;
+        }
+
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry.read_to_string(&mut target)?;
+            create_symlink(Path::new(&target), &out_path)?;
+            summary.links += 1;
+        } else {
+            let mut out_file = File::create(&out_path)
+                .with_context(|| format!("Failed to create {}", out_path.display()))?;
+            summary.bytes += io::copy(&mut entry, &mut out_file)?;
+            summary.files += 1;
+            if let Some(mode) = mode {
+                set_mode(&out_path, mode)?;
+            }
+        }
+    }
+
+    for (dir, mode) in dir_modes.iter().rev() {
+        set_dir_mode(&root, dir, *mode)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)
+        .with_context(|| format!("Failed to create symlink {}", link.display()))
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, link: &Path) -> Result<()> {
+    log::warn!("Skipping symlink {}", link.display());
+    Ok(())
+}
+
+/// Reports the bytes read from the archive file to the progress bar.
+pub(crate) struct CountingReader<'a, R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: &'a TransferProgress,
+}
+
+impl<'a, R> CountingReader<'a, R> {
+    pub(crate) fn new(inner: R, total: u64, progress: &'a TransferProgress) -> Self {
+        Self {
+            inner,
+            read: 0,
+            total,
+            progress,
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        // Zip archives read their central directory on top of the entries
+        self.progress.set_position(self.read.min(self.total));
+        Ok(n)
+    }
+}
+
+impl<R: io::Seek> io::Seek for CountingReader<'_, R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
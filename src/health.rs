@@ -0,0 +1,211 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ssh2::Session;
+
+use crate::remote;
+
+/// How long a host may take to pass its health check after a deploy.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between two failed health check attempts.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Connect and read timeout of a single HTTP probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Least time the health check command gets, even right before the deadline.
+const MIN_COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Checks that decide whether a freshly deployed host is healthy. Both the
+/// remote command and the HTTP probe must pass when both are configured.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    /// Command run on the host over SSH, healthy on exit status 0.
+    pub command: Option<String>,
+    /// `http://` URL probed from the local machine, healthy on a 2xx or 3xx
+    /// response.
+    pub url: Option<String>,
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            command: None,
+            url: None,
+            timeout: DEFAULT_TIMEOUT,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn is_configured(&self) -> bool {
+        self.command.is_some() || self.url.is_some()
+    }
+
+    /// Runs the checks once, giving the command up to `timeout`.
+    pub fn check(&self, session: &Session) -> Result<()> {
+        self.check_within(session, self.timeout)
+    }
+
+    /// Runs the checks once, abandoning a command that has not finished
+    /// within `limit`.
+    fn check_within(&self, session: &Session, limit: Duration) -> Result<()> {
+        if let Some(command) = &self.command {
+            let output = remote::exec_capture_timeout(session, command, limit)?;
+            if !output.success() {
+                let detail = if output.stderr.trim().is_empty() {
+                    output.stdout.trim()
+                } else {
+                    output.stderr.trim()
+                };
+                return Err(anyhow::anyhow!(
+                    "Health check '{}' exited with status {}: {}",
+                    command,
+                    output.exit_status,
+                    detail
+                ));
+            }
+        }
+        if let Some(url) = &self.url {
+            http_probe(url, PROBE_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the checks until they pass or the timeout elapses. A hung
+    /// command is abandoned when the timeout elapses, too.
+    pub fn wait(&self, session: &Session) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        retry_until(self.timeout, self.interval, || {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.check_within(session, remaining.max(MIN_COMMAND_TIMEOUT))
+        })
+    }
+}
+
+/// Calls `check` every `interval` until it succeeds, returning the last error
+/// once `timeout` has elapsed.
+pub fn retry_until(
+    timeout: Duration,
+    interval: Duration,
+    mut check: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match check() {
+            Ok(()) => return Ok(()),
+            Err(e) if Instant::now() + interval > deadline => {
+                return Err(e.context(format!("Still unhealthy after {:?}", timeout)));
+            }
+            Err(_) => thread::sleep(interval),
+        }
+    }
+}
+
+/// Fills the server address `ip` into `{host}` of a health check URL,
+/// bracketing IPv6 addresses as URLs require.
+pub fn health_url(template: &str, ip: &str) -> String {
+    let host = if ip.contains(':') && !ip.starts_with('[') {
+        format!("[{}]", ip)
+    } else {
+        ip.to_string()
+    };
+    template.replace("{host}", &host)
+}
+
+/// Splits an `http://host[:port][/path]` URL into host, port and path.
+pub fn parse_http_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("Only http:// health check URLs are supported: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let invalid = || anyhow::anyhow!("Invalid health check URL: {}", url);
+
+    // Bracketed IPv6 literal, e.g. http://[::1]:8080/health
+    let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        let (host, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+        match after.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if after.is_empty() => (host, None),
+            None => return Err(invalid()),
+        }
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => 80,
+    };
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Sends a `GET` request to `url` and returns the response status code.
+pub fn http_status(url: &str, timeout: Duration) -> Result<u16> {
+    let (host, port, path) = parse_http_url(url)?;
+    let address = (host.as_str(), port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Failed to resolve {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)
+        .with_context(|| format!("Failed to connect to {}", url))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let host_header = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: flash\r\nConnection: close\r\n\r\n",
+        path, host_header
+    )?;
+
+    // Only the status line is needed
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !response.contains(&b'\n') {
+        let n = stream
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read response from {}", url))?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..n]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .filter(|_| status_line.starts_with("HTTP/"))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid HTTP response from {}: {:?}", url, status_line))
+}
+
+/// Probes `url`, failing unless it answers with a 2xx or 3xx status.
+pub fn http_probe(url: &str, timeout: Duration) -> Result<()> {
+    let status = http_status(url, timeout)?;
+    if !(200..400).contains(&status) {
+        return Err(anyhow::anyhow!("{} returned HTTP {}", url, status));
+    }
+    Ok(())
+}
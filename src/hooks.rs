@@ -0,0 +1,116 @@
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use ssh2::Session;
+
+use crate::remote;
+
+/// Values substituted into hook commands.
+///
+/// Placeholders are inserted verbatim, so quote them in the command if the
+/// paths may contain spaces.
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    /// `{remote_file}`: full remote path of the uploaded archive.
+    pub remote_file: String,
+    /// `{remote_dir}`: directory the archive was uploaded to.
+    pub remote_dir: String,
+    /// `{archive}`: file name of the archive.
+    pub archive: String,
+    /// `{format}`: compression format name, e.g. `tar-gz`.
+    pub format: String,
+    /// `{release}`: name of the deployed release, empty outside `flash deploy`.
+    pub release: String,
+    /// `{release_dir}`: remote directory of the deployed release.
+    pub release_dir: String,
+}
+
+impl HookContext {
+    pub fn expand(&self, command: &str) -> String {
+        command
+            .replace("{remote_file}", &self.remote_file)
+            .replace("{remote_dir}", &self.remote_dir)
+            .replace("{archive}", &self.archive)
+            .replace("{format}", &self.format)
+            .replace("{release_dir}", &self.release_dir)
+            .replace("{release}", &self.release)
+    }
+}
+
+/// Runs `hooks` one after another on the server, stopping at the first one
+/// that exits with a non-zero status.
+pub fn run_remote_hooks(session: &Session, hooks: &[String], context: &HookContext) -> Result<()> {
+    for hook in hooks {
+        let command = context.expand(hook);
+        println!("▶ Running remote hook: {}", command);
+        let status = remote::exec_streaming(session, &command)?;
+        if status != 0 {
+            return Err(anyhow::anyhow!(
+                "Remote hook `{}` exited with status {}",
+                command,
+                status
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Like [`run_remote_hooks`], but collects the output of each hook and passes
+/// it to `log` line by line, for when several hosts share the terminal.
+pub fn run_remote_hooks_logged(
+    session: &Session,
+    hooks: &[String],
+    context: &HookContext,
+    log: impl Fn(&str),
+) -> Result<()> {
+    for hook in hooks {
+        let command = context.expand(hook);
+        log(&format!("▶ Running remote hook: {}", command));
+        let output = remote::exec_capture(session, &command)?;
+        for line in output.stdout.lines().chain(output.stderr.lines()) {
+            log(line);
+        }
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "Remote hook `{}` exited with status {}",
+                command,
+                output.exit_status
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `hooks` one after another on this machine through the platform shell,
+/// with `env` added to their environment. Stops at the first failing hook.
+pub fn run_local_hooks(hooks: &[String], env: &[(&str, String)]) -> Result<()> {
+    for hook in hooks {
+        println!("▶ Running local hook: {}", hook);
+        // stdin may be the data `flash send -` uploads, which a hook must not consume
+        let status = shell_command(hook)
+            .envs(env.iter().map(|(key, value)| (*key, value)))
+            .stdin(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to start local hook `{}`", hook))?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "Local hook `{}` failed with {}",
+                hook,
+                status
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
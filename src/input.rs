@@ -28,10 +28,11 @@ pub fn prompt_ip_address(prompt: &str) -> String {
     }
 }
 
-pub fn prompt_server_selection(config: &Config) -> Option<ServerConfig> {
+pub fn prompt_server_selection(config: &Config) -> Option<Vec<ServerConfig>> {
     let servers = config.list_servers();
+    let groups = config.list_groups();
 
-    if servers.is_empty() {
+    if servers.is_empty() && groups.is_empty() {
         println!("No servers configured.");
         return None;
     }
@@ -47,10 +48,22 @@ pub fn prompt_server_selection(config: &Config) -> Option<ServerConfig> {
             server.port.unwrap_or(22)
         );
     }
+    if !groups.is_empty() {
+        println!("Server groups:");
+        for (i, (name, group)) in groups.iter().enumerate() {
+            println!(
+                " {}. {} ({})",
+                servers.len() + i + 1,
+                name,
+                group.servers.join(", ")
+            );
+        }
+    }
+    let choices = servers.len() + groups.len();
     loop {
         print!(
             "Select a server by number (1-{}) or 'c' for custom: ",
-            servers.len()
+            choices
         );
         io::stdout().flush().unwrap();
 
@@ -65,14 +78,21 @@ pub fn prompt_server_selection(config: &Config) -> Option<ServerConfig> {
         if let Ok(choice) = input.parse::<usize>() {
             if choice >= 1 && choice <= servers.len() {
                 let (_, server) = &servers[choice - 1];
-                return Some((*server).clone());
+                return Some(vec![(*server).clone()]);
+            }
+            if choice > servers.len() && choice <= choices {
+                let (name, group) = groups[choice - servers.len() - 1];
+                match config.group_members(name, group) {
+                    Ok(members) => return Some(members),
+                    Err(e) => println!("{}", e),
+                }
             }
         }
-        print!("Invalid choice. Please try again.\n");
+        println!("Invalid choice. Please try again.");
     }
 }
 
-pub fn get_server_config(config_opt: Option<Config>) -> Option<ServerConfig> {
+pub fn get_server_config(config_opt: Option<Config>) -> Option<Vec<ServerConfig>> {
     match config_opt {
         Some(config) => {
             if let Some(default_server) = config.get_default_server() {
@@ -83,7 +103,7 @@ pub fn get_server_config(config_opt: Option<Config>) -> Option<ServerConfig> {
 
                 let use_default = prompt_string("Use this server? (y/n): ");
                 if use_default.to_lowercase().starts_with('y') {
-                    return Some(default_server.clone());
+                    return Some(vec![default_server.clone()]);
                 }
             }
             prompt_server_selection(&config)
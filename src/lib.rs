@@ -1,5 +1,19 @@
+pub mod checksum;
+pub mod cleanup;
 pub mod compress;
 pub mod config;
+pub mod deploy;
+pub mod download;
+pub mod extract;
+pub mod health;
+pub mod hooks;
 pub mod input;
 pub mod loading;
+pub mod remote;
+pub mod retention;
+pub mod sources;
+pub mod template;
+pub mod throttle;
+pub mod units;
 pub mod upload;
+pub mod verify;
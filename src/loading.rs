@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 pub struct LoadingSpinner {
     spinner: ProgressBar,
@@ -11,7 +11,7 @@ impl LoadingSpinner {
         let spinner = ProgressBar::new_spinner();
         spinner.set_style(
             ProgressStyle::default_spinner()
-                .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ")
+                .tick_chars(SPINNER_TICKS)
                 .template("{spinner:.cyan} {msg}")
                 .unwrap(),
         );
@@ -42,3 +42,150 @@ impl Drop for LoadingSpinner {
         self.spinner.finish_and_clear();
     }
 }
+
+const SPINNER_TICKS: &str = "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ";
+
+/// Progress display of a single upload.
+///
+/// Shows a spinner while connecting and verifying and a byte progress bar
+/// while transferring. When several hosts upload at once, each gets its own
+/// line in a shared [`MultiProgress`], prefixed with the host name.
+#[derive(Clone)]
+pub struct TransferProgress {
+    bar: ProgressBar,
+    shared: bool,
+}
+
+impl TransferProgress {
+    pub fn new() -> Self {
+        let progress = Self {
+            bar: ProgressBar::new_spinner(),
+            shared: false,
+        };
+        progress.show_spinner();
+        progress
+    }
+
+    /// Adds a line for `host` to `multi`.
+    pub fn in_multi(multi: &MultiProgress, host: &str) -> Self {
+        let bar = multi.add(ProgressBar::new_spinner());
+        bar.set_prefix(host.to_string());
+        let progress = Self { bar, shared: true };
+        progress.show_spinner();
+        progress
+    }
+
+    /// Whether this upload shares the terminal with other uploads.
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    fn template(&self, body: &str) -> String {
+        if self.shared {
+            format!("{{prefix:.bold}} {}", body)
+        } else {
+            body.to_string()
+        }
+    }
+
+    fn show_spinner(&self) {
+        self.bar.set_style(
+            ProgressStyle::default_spinner()
+                .tick_chars(SPINNER_TICKS)
+                .template(&self.template("{spinner:.cyan} {msg}"))
+                .unwrap(),
+        );
+        self.bar.enable_steady_tick(Duration::from_millis(80));
+    }
+
+    pub fn set_message(&self, message: &str) {
+        self.bar.set_message(message.to_string());
+    }
+
+    /// Switches back to the spinner with `message`, e.g. after a transfer.
+    pub fn spin(&self, message: &str) {
+        self.show_spinner();
+        self.set_message(message);
+    }
+
+    /// Switches to a byte progress bar for a transfer of `total` bytes, of
+    /// which `position` are already done.
+    pub fn start_transfer(&self, total: u64, position: u64) {
+        self.bar.set_length(total);
+        self.bar.set_position(position);
+        self.bar.reset_eta();
+        self.bar.set_style(
+            ProgressStyle::default_bar()
+                .template(&self.template(
+                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta})",
+                ))
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+    }
+
+    /// Switches to a byte counter with throughput, for a transfer whose size
+    /// is not known in advance.
+    pub fn start_stream(&self) {
+        self.bar.unset_length();
+        self.bar.set_position(0);
+        self.bar.set_style(
+            ProgressStyle::default_spinner()
+                .tick_chars(SPINNER_TICKS)
+                .template(&self.template(
+                    "{spinner:.green} [{elapsed_precise}] {bytes} ({binary_bytes_per_sec})",
+                ))
+                .unwrap(),
+        );
+    }
+
+    pub fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+    }
+
+    /// Adds `delta` transferred bytes, for transfers split across threads.
+    pub fn advance(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    /// Hides the display while `f` runs, e.g. to let a command write to the
+    /// terminal directly.
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.bar.suspend(f)
+    }
+
+    /// Prints `message` above the progress display, or plainly when the
+    /// display is hidden because the output is not a terminal.
+    pub fn println(&self, message: &str) {
+        let line = if self.shared {
+            format!("[{}] {}", self.bar.prefix(), message)
+        } else {
+            message.to_string()
+        };
+        if self.bar.is_hidden() {
+            println!("{}", line);
+        } else {
+            self.bar.println(line);
+        }
+    }
+
+    pub fn finish_with_success(&self, message: &str) {
+        self.spin("");
+        self.bar.finish_with_message(format!("✅ {}", message));
+    }
+
+    pub fn finish_with_error(&self, message: &str) {
+        self.spin("");
+        self.bar.finish_with_message(format!("❌ {}", message));
+    }
+
+    pub fn finish_and_clear(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+impl Default for TransferProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
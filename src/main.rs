@@ -1,181 +1,2379 @@
-use std::path::Path;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Ok;
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use indicatif::{HumanBytes, MultiProgress};
 use log::{error, info, warn};
+use ssh2::Session;
 
+use flash::checksum::ChecksumAlgorithm;
+use flash::cleanup;
 use flash::compress::{self, CompressionFormat};
-use flash::config::Config;
+use flash::config::{Config, ServerConfig};
+use flash::deploy::{self, ReleaseLayout};
+use flash::download::{self, DownloadOptions};
+use flash::extract::{self, ArchiveEntry, EntryKind};
+use flash::health::{self, HealthCheck};
+use flash::hooks::{self, HookContext};
 use flash::input;
-use flash::loading::LoadingSpinner;
-use flash::upload;
+use flash::loading::{LoadingSpinner, TransferProgress};
+use flash::remote;
+use flash::retention::{self, RemoteArchive};
+use flash::sources;
+use flash::template::RemoteNameContext;
+use flash::throttle::{self, Throttle};
+use flash::units;
+use flash::upload::{self, UploadOutcome};
+use flash::verify;
 
 #[derive(Parser, Debug)]
-#[command(version, about = "Toolkit for uploading compressed file/folder.", long_about = None)]
+#[command(
+    version,
+    about = "Toolkit for uploading compressed file/folder.",
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    arg_required_else_help = true
+)]
 struct Args {
-    #[arg(short, long, required_unless_present = "init_config")]
-    path: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// `flash --path <PATH> ...` without a subcommand is `flash push`
+    #[command(flatten, next_help_heading = "Push options (same as `flash push`)")]
+    push: PushArgs,
+
+    #[arg(long, action, exclusive = true, help = "Same as `flash config init`")]
+    init_config: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct PushArgs {
+    // Not `required` on its own so the flattened top-level form parses with a
+    // subcommand
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        required_unless_present = "paths",
+        help = "File, directory or glob pattern to upload (repeatable)"
+    )]
+    path: Vec<String>,
+
+    #[arg(
+        value_name = "PATHS",
+        help = "More files, directories or quoted glob patterns, e.g. 'logs/*.log'"
+    )]
+    paths: Vec<String>,
+
+    #[arg(
+        long,
+        action,
+        help = "Upload every path as its own archive instead of bundling them into one"
+    )]
+    separate: bool,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Compression format: zip, tar, tar-gz [default: zip, or the server's format]"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(long, action, help = "Keep the replaced remote file as <name>.prev")]
+    keep_previous: bool,
+
+    #[arg(long, help = "Extract the uploaded archive into this remote directory")]
+    extract_to: Option<String>,
+
+    #[arg(
+        long,
+        action,
+        requires = "extract_to",
+        help = "Remove the uploaded archive after extracting it"
+    )]
+    remove_archive: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Decode the archive and compare it with the source before uploading"
+    )]
+    verify_archive: bool,
+
+    #[command(flatten)]
+    archive: ArchiveArgs,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Name of the uploaded file, e.g. '{name}-{date:%Y%m%d}' [default: the server's remote_name]"
+    )]
+    remote_name: Option<String>,
+
+    #[command(flatten)]
+    local_hooks: LocalHookArgs,
+
+    #[command(flatten)]
+    transfer: TransferArgs,
+
+    #[arg(
+        long,
+        default_value_t = 4,
+        help = "Maximum number of servers uploaded to in parallel"
+    )]
+    jobs: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct ConnectionArgs {
+    #[arg(long)]
+    ip: Option<String>,
+
+    #[arg(long)]
+    port: Option<u16>,
+
+    #[arg(long)]
+    username: Option<String>,
+
+    #[arg(long)]
+    password: Option<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Configured server(s) to use, comma-separated"
+    )]
+    server: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ArchiveArgs {
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write the archive into DIR and keep it [default: a temporary file removed after upload]"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        action,
+        help = "Keep the archive after uploading, in the current directory unless --output-dir is given"
+    )]
+    keep_archive: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Replace an existing file where a kept archive is written"
+    )]
+    overwrite: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct LocalHookArgs {
+    #[arg(
+        long = "pre-compress",
+        value_name = "COMMAND",
+        help = "Run a local command before compressing (repeatable)"
+    )]
+    pre_compress: Vec<String>,
+
+    #[arg(
+        long = "pre-upload",
+        value_name = "COMMAND",
+        help = "Run a local command before uploading (repeatable)"
+    )]
+    pre_upload: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct TransferArgs {
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Upload large files over this many SSH connections in parallel"
+    )]
+    connections: usize,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "256K",
+        value_parser = parse_buffer_size,
+        help = "Size of the transfer buffers, e.g. 64K or 1M"
+    )]
+    buffer_size: usize,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = parse_limit_rate,
+        help = "Limit the upload to RATE bytes per second per server, e.g. 500K or 5M"
+    )]
+    limit_rate: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read the bandwidth limit from FILE whenever it changes during the upload"
+    )]
+    limit_rate_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Hash uploads are verified with [default: sha256, or the server's checksum]"
+    )]
+    checksum: Option<ChecksumAlgorithm>,
+}
+
+fn parse_limit_rate(value: &str) -> Result<u64, String> {
+    throttle::parse_rate(value).map_err(|e| e.to_string())
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    match units::parse_size(value) {
+        Ok(0) => Err("must be greater than zero".to_string()),
+        Ok(size) => usize::try_from(size).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compress a file or directory and upload it
+    Push(Box<PushArgs>),
+    /// Compress a file or directory without uploading it
+    Pack(PackArgs),
+    /// Upload an existing file as it is, e.g. a prebuilt package
+    Send(Box<SendArgs>),
+    /// Manage the configuration file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Inspect the configured servers and groups
+    #[command(subcommand)]
+    Servers(ServersCommand),
+    /// Upload into a new release directory and switch the `current` symlink to it
+    Deploy(Box<DeployArgs>),
+    /// Point the `current` symlink back at an earlier release
+    Rollback(RollbackArgs),
+    /// Remove old archives from the server according to its retention policy
+    Prune(PruneArgs),
+    /// Download a remote file, or a remote directory as an archive
+    Pull(PullArgs),
+    /// Extract a local archive
+    Extract(ExtractArgs),
+    /// List the contents of a local archive
+    List(ListArgs),
+    /// Check that a local archive decodes completely and matches its source
+    Verify(VerifyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct PackArgs {
+    #[arg(value_name = "PATH", help = "File or directory to compress")]
+    path: String,
+
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Archive to write [default: <name>.<ext> in the current directory]"
+    )]
+    output: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Compression format: zip, tar, tar-gz [default: from --output, or zip]"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[arg(long, action, help = "Replace the output file if it exists")]
+    force: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Decode the archive and compare it with the source"
+    )]
+    verify_archive: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct SendArgs {
+    #[arg(value_name = "FILE", help = "File to upload, or - for standard input")]
+    file: String,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Name of the uploaded file when reading standard input"
+    )]
+    name: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Compress standard input on the way: zip, or gzip for tar-gz"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(long, action, help = "Keep the replaced remote file as <name>.prev")]
+    keep_previous: bool,
+
+    #[arg(
+        long,
+        help = "Extract the uploaded file into this remote directory; it must be a zip, tar or tar.gz archive"
+    )]
+    extract_to: Option<String>,
+
+    #[arg(
+        long,
+        action,
+        requires = "extract_to",
+        help = "Remove the uploaded archive after extracting it"
+    )]
+    remove_archive: bool,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Name of the uploaded file, e.g. '{name}-{date:%Y%m%d}' [default: the server's remote_name]"
+    )]
+    remote_name: Option<String>,
+
+    #[arg(
+        long = "pre-upload",
+        value_name = "COMMAND",
+        help = "Run a local command before uploading (repeatable)"
+    )]
+    pre_upload: Vec<String>,
+
+    #[command(flatten)]
+    transfer: TransferArgs,
+
+    #[arg(
+        long,
+        default_value_t = 4,
+        help = "Maximum number of servers uploaded to in parallel"
+    )]
+    jobs: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Write an example configuration file
+    Init {
+        #[arg(long, action, help = "Replace an existing configuration file")]
+        force: bool,
+    },
+    /// Print the path of the configuration file in use
+    Path,
+    /// Check that the configuration file parses and its groups resolve
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+enum ServersCommand {
+    /// List the configured servers and groups
+    List,
+    /// Show the settings of a server, or of every member of a group
+    Show {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct DeployArgs {
+    #[arg(short, long)]
+    path: String,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Compression format: zip, tar, tar-gz [default: tar-gz, or the server's format]"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(long, help = "Number of releases to keep on the server")]
+    keep_releases: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Number of servers deployed to at a time"
+    )]
+    batch_size: usize,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[arg(
+        long,
+        action,
+        help = "Decode the archive and compare it with the source before uploading"
+    )]
+    verify_archive: bool,
+
+    #[command(flatten)]
+    archive: ArchiveArgs,
+
+    #[command(flatten)]
+    local_hooks: LocalHookArgs,
+
+    #[command(flatten)]
+    transfer: TransferArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct HealthArgs {
+    #[arg(
+        long = "health-check",
+        value_name = "COMMAND",
+        help = "Command run on each server after deploying; non-zero fails the deploy"
+    )]
+    command: Option<String>,
+
+    #[arg(
+        long = "health-url",
+        value_name = "URL",
+        help = "http:// URL probed after deploying, {host} is replaced by the server address"
+    )]
+    url: Option<String>,
+
+    #[arg(
+        long = "health-timeout",
+        value_name = "DURATION",
+        help = "How long a server may take to become healthy [default: 30s]"
+    )]
+    timeout: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RollbackArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(long, value_name = "RELEASE", help = "Release to roll back to")]
+    to: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct PullArgs {
+    #[arg(
+        value_name = "REMOTE_PATH",
+        help = "Remote file or directory to download"
+    )]
+    remote_path: String,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Local file to save to [default: the remote name, plus the archive extension for directories]"
+    )]
+    output: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Archive format for directories: tar, tar-gz [default: tar-gz]"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Extract the downloaded archive into this local directory"
+    )]
+    extract_to: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Hash the download is verified with [default: sha256]"
+    )]
+    checksum: Option<ChecksumAlgorithm>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "256K",
+        value_parser = parse_buffer_size,
+        help = "Size of the transfer buffers, e.g. 64K or 1M"
+    )]
+    buffer_size: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct PruneArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[arg(long, action, help = "Only list the archives that would be removed")]
+    dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExtractArgs {
+    #[arg(value_name = "ARCHIVE")]
+    archive: String,
+
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "DIR",
+        default_value = ".",
+        help = "Directory to extract into"
+    )]
+    directory: String,
 
     #[arg(
         short,
         long,
         value_enum,
-        default_value = "zip",
-        help = "Compression format: zip, tar, tar-gz"
+        help = "Archive format: zip, tar, tar-gz [default: from the file extension]"
     )]
     format: Option<CompressionFormat>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    #[arg(value_name = "ARCHIVE")]
+    archive: String,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Archive format: zip, tar, tar-gz [default: from the file extension]"
+    )]
+    format: Option<CompressionFormat>,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    #[arg(value_name = "ARCHIVE")]
+    archive: String,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Archive format: zip, tar, tar-gz [default: from the file extension]"
+    )]
+    format: Option<CompressionFormat>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File or directory the archive was created from, to compare entries with"
+    )]
+    source: Option<String>,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    if args.init_config {
+        return run_config_init(false);
+    }
+    let command = args
+        .command
+        .unwrap_or_else(|| Command::Push(Box::new(args.push)));
+    // Only commands that talk to servers read the configuration file
+    let load_config = || {
+        Config::load().unwrap_or_else(|e| {
+            warn!(
+                "Failed to load config: {}. Run `flash config check` for details.",
+                e
+            );
+            None
+        })
+    };
+
+    match command {
+        Command::Push(push_args) => run_push(*push_args, load_config()),
+        Command::Pack(pack_args) => run_pack(pack_args),
+        Command::Send(send_args) => run_send(*send_args, load_config()),
+        Command::Config(config_command) => run_config(config_command),
+        Command::Servers(servers_command) => run_servers(servers_command, load_config()),
+        Command::Deploy(deploy_args) => run_deploy(*deploy_args, load_config()),
+        Command::Rollback(rollback_args) => run_rollback(rollback_args, load_config()),
+        Command::Prune(prune_args) => run_prune(prune_args, load_config()),
+        Command::Pull(pull_args) => run_pull(pull_args, load_config()),
+        Command::Extract(extract_args) => run_extract(extract_args),
+        Command::List(list_args) => run_list(list_args),
+        Command::Verify(verify_args) => run_verify(verify_args),
+    }
+}
+
+fn run_config(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Init { force } => run_config_init(force),
+        ConfigCommand::Path => {
+            println!("{}", Config::path()?.display());
+            Ok(())
+        }
+        ConfigCommand::Check => {
+            let path = Config::path()?;
+            let config = Config::load()?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No configuration file at {}, run `flash config init`",
+                    path.display()
+                )
+            })?;
+            config.validate()?;
+            println!(
+                "✅ {} is valid: {} servers, {} groups",
+                path.display(),
+                config.servers.len(),
+                config.groups.len()
+            );
+            Ok(())
+        }
+    }
+}
+
+fn run_config_init(force: bool) -> Result<()> {
+    let path = Config::path()?;
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        ));
+    }
+    Config::create_example()
+}
+
+fn run_servers(command: ServersCommand, config: Option<Config>) -> Result<()> {
+    let config = config
+        .ok_or_else(|| anyhow::anyhow!("No configuration file found, run `flash config init`"))?;
+    match command {
+        ServersCommand::List => {
+            let default = config.default.as_ref().and_then(|d| d.server.as_deref());
+            println!("Servers:");
+            for (name, server) in config.list_servers() {
+                let marker = if Some(name.as_str()) == default {
+                    " (default)"
+                } else {
+                    ""
+                };
+                println!(
+                    "  {:<16} {}@{}:{}  {}{}",
+                    name,
+                    server.username,
+                    server.ip,
+                    server.port(),
+                    server.remote_dir(),
+                    marker
+                );
+            }
+            let groups = config.list_groups();
+            if !groups.is_empty() {
+                println!("\nGroups:");
+                for (name, group) in groups {
+                    println!("  {:<16} {}", name, group.servers.join(", "));
+                }
+            }
+            Ok(())
+        }
+        ServersCommand::Show { name } => {
+            let servers = config.resolve(&name)?;
+            let keys = match config.get_group(&name) {
+                Some(group) if config.get_server(&name).is_none() => group.servers.clone(),
+                _ => vec![name],
+            };
+            for (key, mut server) in keys.iter().zip(servers) {
+                if server.password.is_some() {
+                    server.password = Some("********".to_string());
+                }
+                let settings =
+                    toml::to_string(&server).context("Failed to format server settings")?;
+                println!("[servers.{}]\n{}", key, settings);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run_push(args: PushArgs, config: Option<Config>) -> Result<()> {
+    let servers = resolve_servers(args.connection, config)?;
+    check_retention_dirs(&servers)?;
+    let patterns: Vec<String> = args.path.into_iter().chain(args.paths).collect();
+    let inputs = sources::expand(&patterns)?;
+
+    let format = effective_format(args.format, &servers, CompressionFormat::default())?;
+
+    // One archive for all inputs, or with --separate one for each
+    let groups: Vec<Vec<PathBuf>> = if args.separate {
+        inputs.into_iter().map(|input| vec![input]).collect()
+    } else {
+        vec![inputs]
+    };
+    let mut archive_names = Vec::with_capacity(groups.len());
+    // Remote path of every archive on every server
+    let mut remote_paths: Vec<Vec<String>> = vec![Vec::new(); servers.len()];
+    for group in &groups {
+        let (name, context) = archive_for(group, format)?;
+        if archive_names.contains(&name) {
+            return Err(anyhow::anyhow!(
+                "Two inputs would both be uploaded as {}",
+                name
+            ));
+        }
+        let paths = remote_paths_for(&servers, args.remote_name.as_deref(), &context, &name)?;
+        for ((server_paths, path), server) in remote_paths.iter_mut().zip(paths).zip(&servers) {
+            // A remote_name template without {name} renders alike for every input
+            if server_paths.contains(&path) {
+                return Err(anyhow::anyhow!(
+                    "Two archives would both be uploaded to {} on {}, use {{name}} in the remote name",
+                    path,
+                    server.name
+                ));
+            }
+            server_paths.push(path);
+        }
+        archive_names.push(name);
+    }
+
+    // Passwords are prompted for up front so the uploads can run unattended
+    let targets: Vec<upload::SshTarget> = servers.iter().map(ssh_target).collect();
+
+    let mut archives: Vec<LocalArchive> = Vec::with_capacity(groups.len());
+    for name in &archive_names {
+        match LocalArchive::create(&args.archive, name) {
+            Ok(archive) => archives.push(archive),
+            Err(e) => {
+                archives.iter().for_each(LocalArchive::discard);
+                return Err(e);
+            }
+        }
+    }
+    let output_paths: Vec<String> = archives.iter().map(|a| a.path.clone()).collect();
+
+    let hook_env = hook_env(
+        &patterns.join(","),
+        &output_paths.join(","),
+        Some(format),
+        &servers,
+        &remote_paths
+            .iter()
+            .map(|paths| paths.join(","))
+            .collect::<Vec<_>>(),
+    );
+    let (pre_compress, pre_upload) = local_hooks(&servers, args.local_hooks);
+    let prepared = hooks::run_local_hooks(&pre_compress, &hook_env)
+        .context("Pre-compress hook failed")
+        .and_then(|_| {
+            groups
+                .iter()
+                .zip(&output_paths)
+                .try_for_each(|(group, output_path)| {
+                    prepare_archive(
+                        group,
+                        output_path,
+                        format,
+                        args.verify_archive,
+                        &hook_env,
+                        &[],
+                        &[],
+                    )
+                })
+        })
+        .and_then(|_| {
+            hooks::run_local_hooks(&pre_upload, &hook_env).context("Pre-upload hook failed")
+        });
+    if let Err(e) = prepared {
+        archives.iter().for_each(LocalArchive::discard);
+        return Err(e);
+    }
+    archives.iter().for_each(LocalArchive::compressed);
+
+    let plan = PushPlan {
+        files: &output_paths,
+        format: Some(format),
+        keep_previous: args.keep_previous,
+        extract_to: args.extract_to.as_deref(),
+        remove_archive: args.remove_archive,
+        remote_name: args.remote_name.as_deref(),
+        transfer: &args.transfer,
+    };
+    let result = deliver(&servers, &targets, &remote_paths, &plan, args.jobs);
+    for archive in &archives {
+        archive.finish(result.is_ok());
+    }
+    result
+}
+
+/// Name of the archive `inputs` are compressed into and the context its
+/// remote name is rendered with. A bundle is named after the directory
+/// containing its inputs.
+fn archive_for(
+    inputs: &[PathBuf],
+    format: CompressionFormat,
+) -> Result<(String, RemoteNameContext)> {
+    if let [input] = inputs {
+        let name = format!("{}.{}", sources::file_name(input)?, format.extension());
+        return Ok((name, RemoteNameContext::for_archive(input, format)));
+    }
+    // Fails early on inputs that would clash inside the archive
+    sources::entry_names(inputs)?;
+    let mut context = RemoteNameContext::for_archive(&sources::common_dir(inputs)?, format);
+    if context.name.is_empty() {
+        context.name = "bundle".to_string();
+    }
+    Ok((format!("{}.{}", context.name, format.extension()), context))
+}
+
+/// Local file a push or deploy compresses into.
+struct LocalArchive {
+    path: String,
+    /// Unique directory holding a temporary archive.
+    temp_dir: Option<PathBuf>,
+}
+
+impl LocalArchive {
+    /// A temporary archive named `name`, or with `--output-dir` or
+    /// `--keep-archive` one that is kept. Kept archives never replace an
+    /// existing file unless `--overwrite` is given.
+    fn create(args: &ArchiveArgs, name: &str) -> Result<Self> {
+        cleanup::install_interrupt_handler();
+        if args.output_dir.is_none() && !args.keep_archive {
+            let temp_dir = cleanup::unique_temp_dir()?;
+            return Ok(Self {
+                path: temp_dir.join(name).to_string_lossy().into_owned(),
+                temp_dir: Some(temp_dir),
+            });
+        }
+
+        let dir = args
+            .output_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(name);
+        if path.exists() && !args.overwrite {
+            return Err(anyhow::anyhow!(
+                "{} already exists, pass --overwrite to replace it or choose another --output-dir",
+                path.display()
+            ));
+        }
+        // Removed on Ctrl-C until it is complete
+        cleanup::register(&path);
+        Ok(Self {
+            path: path.to_string_lossy().into_owned(),
+            temp_dir: None,
+        })
+    }
+
+    /// Called once the archive is complete: from now on, Ctrl-C only
+    /// removes temporary archives.
+    fn compressed(&self) {
+        if self.temp_dir.is_none() {
+            cleanup::unregister(Path::new(&self.path));
+        }
+    }
+
+    /// Removes the archive after compressing it failed.
+    fn discard(&self) {
+        let path = self
+            .temp_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&self.path));
+        if let Err(e) = cleanup::remove(&path) {
+            warn!("{:#}", e);
+        }
+    }
+
+    /// Removes a temporary archive once it is uploaded. After a failed upload
+    /// it is kept, so it can be sent again with `flash send`.
+    fn finish(&self, uploaded: bool) {
+        match &self.temp_dir {
+            Some(temp_dir) if uploaded => {
+                if let Err(e) = cleanup::remove(temp_dir) {
+                    warn!("{:#}", e);
+                }
+            }
+            Some(temp_dir) => {
+                cleanup::unregister(temp_dir);
+                println!("📦 Archive kept at {}", self.path);
+            }
+            None => {}
+        }
+    }
+}
+
+fn run_pack(args: PackArgs) -> Result<()> {
+    let format = args
+        .format
+        .or_else(|| {
+            args.output
+                .as_deref()
+                .and_then(|output| CompressionFormat::from_path(Path::new(output)))
+        })
+        .unwrap_or_default();
+    let output_path = match args.output {
+        Some(output) => output,
+        None => archive_name(&args.path, format)?,
+    };
+    if Path::new(&output_path).exists() && !args.force {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --force to replace it",
+            output_path
+        ));
+    }
+
+    prepare_archive(
+        &[PathBuf::from(&args.path)],
+        &output_path,
+        format,
+        args.verify_archive,
+        &[],
+        &[],
+        &[],
+    )?;
+    let size = fs::metadata(&output_path)?.len();
+    println!("📦 {} ({})", output_path, HumanBytes(size));
+    Ok(())
+}
+
+fn run_send(args: SendArgs, config: Option<Config>) -> Result<()> {
+    let stdin = args.file == "-";
+    let local = Path::new(&args.file);
+    let remote_name = if stdin {
+        if io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Standard input is a terminal, pipe the data into `flash send -`"
+            ));
+        }
+        // Server selection and host prompts would read the piped data
+        if args.connection.server.is_empty()
+            && (args.connection.ip.is_none() || args.connection.username.is_none())
+        {
+            return Err(anyhow::anyhow!(
+                "Sending standard input needs --server, or --ip and --username"
+            ));
+        }
+        let name = args
+            .name
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Sending standard input needs --name"))?;
+        stream_name(name, args.format)?
+    } else {
+        if args.name.is_some() || args.format.is_some() {
+            return Err(anyhow::anyhow!(
+                "--name and --format only apply to standard input (`flash send -`)"
+            ));
+        }
+        if !local.is_file() {
+            return Err(anyhow::anyhow!("Not a file: {}", args.file));
+        }
+        sources::file_name(local)?
+    };
+    let format = CompressionFormat::from_path(Path::new(&remote_name));
+    if args.extract_to.is_some() && format.is_none() {
+        return Err(anyhow::anyhow!(
+            "--extract-to needs a zip, tar or tar.gz archive, got {}",
+            remote_name
+        ));
+    }
+
+    let servers = resolve_servers(args.connection, config)?;
+    check_retention_dirs(&servers)?;
+    if stdin && servers.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "Standard input can only be sent to one server, got {}",
+            servers.len()
+        ));
+    }
+    let context = if stdin {
+        RemoteNameContext::for_file(Path::new(&remote_name))
+    } else {
+        RemoteNameContext::for_file(local)
+    };
+    let remote_paths = remote_paths_for(
+        &servers,
+        args.remote_name.as_deref(),
+        &context,
+        &remote_name,
+    )?;
+    let targets: Vec<upload::SshTarget> = servers.iter().map(ssh_target).collect();
+
+    let hooks = LocalHookArgs {
+        pre_compress: Vec::new(),
+        pre_upload: args.pre_upload,
+    };
+    let (_, pre_upload) = local_hooks(&servers, hooks);
+    let hook_env = hook_env(&args.file, &args.file, format, &servers, &remote_paths);
+    hooks::run_local_hooks(&pre_upload, &hook_env).context("Pre-upload hook failed")?;
+
+    let plan = PushPlan {
+        files: std::slice::from_ref(&args.file),
+        format,
+        keep_previous: args.keep_previous,
+        extract_to: args.extract_to.as_deref(),
+        remove_archive: args.remove_archive,
+        remote_name: args.remote_name.as_deref(),
+        transfer: &args.transfer,
+    };
+    if stdin {
+        return send_stream(
+            &servers[0],
+            &targets[0],
+            &remote_paths[0],
+            &plan,
+            args.format,
+        );
+    }
+    let remote_paths: Vec<Vec<String>> = remote_paths.into_iter().map(|path| vec![path]).collect();
+    deliver(&servers, &targets, &remote_paths, &plan, args.jobs)
+}
+
+/// Name of a file streamed from standard input: `name`, with the extension
+/// of the codec it is compressed with appended unless already present.
+fn stream_name(name: &str, compression: Option<CompressionFormat>) -> Result<String> {
+    let extension = match compression {
+        None => return Ok(name.to_string()),
+        Some(CompressionFormat::Zip) => ".zip",
+        Some(CompressionFormat::TarGz) => ".gz",
+        Some(CompressionFormat::Tar) => {
+            return Err(anyhow::anyhow!(
+                "Standard input cannot be archived as tar, use --format zip or tar-gz"
+            ))
+        }
+    };
+    if name.to_lowercase().ends_with(extension) {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}{}", name, extension))
+    }
+}
+
+/// Uploads standard input to one host without a local copy and runs the
+/// follow-up steps of a push. The input cannot be read again, so there are
+/// no retries.
+fn send_stream(
+    server: &ServerConfig,
+    target: &upload::SshTarget,
+    remote_path: &str,
+    plan: &PushPlan,
+    compression: Option<CompressionFormat>,
+) -> Result<()> {
+    let progress = TransferProgress::new();
+    let sent = upload::open_session(target, &progress).and_then(|session| {
+        // A zipped stream holds one entry, named like the upload without .zip
+        let file_name = Path::new(remote_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let entry_name = file_name
+            .strip_suffix(".zip")
+            .unwrap_or(&file_name)
+            .to_string();
+        let read = upload::upload_stream(
+            &session,
+            io::stdin(),
+            remote_path,
+            compression,
+            &entry_name,
+            &upload_options(server, plan.keep_previous, plan.transfer)?,
+            &progress,
+        )
+        .with_context(|| format!("Failed to upload standard input to {}", remote_path))?;
+        after_upload(&session, server, &[("-", remote_path)], plan, &progress)?;
+        Ok(read)
+    });
+    match &sent {
+        Ok(read) => progress.finish_with_success(&format!(
+            "Uploaded {} from standard input to ({}) {}",
+            HumanBytes(*read),
+            target.ip,
+            remote_path
+        )),
+        Err(_) => progress.finish_with_error("Upload failed"),
+    }
+    sent.map(|_| ())
+}
+
+/// Uploads the local files of `plan` to every server, in parallel when there
+/// are several. `remote_paths` holds the destination of each file per server.
+fn deliver(
+    servers: &[ServerConfig],
+    targets: &[upload::SshTarget],
+    remote_paths: &[Vec<String>],
+    plan: &PushPlan,
+    jobs: usize,
+) -> Result<()> {
+    if servers.len() == 1 {
+        let progress = TransferProgress::new();
+        let result = push_to_host(&servers[0], &targets[0], &remote_paths[0], plan, &progress);
+        upload::finish_upload(
+            &progress,
+            result.as_ref().ok().copied(),
+            &targets[0],
+            &remote_paths[0].join(", "),
+        );
+        return result.map(|_| ());
+    }
+
+    fan_out(servers, targets, remote_paths, plan, jobs)
+}
+
+/// What to do with the local files on each host.
+struct PushPlan<'a> {
+    /// Files uploaded to every host, over one session per host.
+    files: &'a [String],
+    /// Format of the files, `None` if they are not archives.
+    format: Option<CompressionFormat>,
+    keep_previous: bool,
+    extract_to: Option<&'a str>,
+    remove_archive: bool,
+    /// The `--remote-name` template, which retention matches archives by.
+    remote_name: Option<&'a str>,
+    transfer: &'a TransferArgs,
+}
+
+/// Uploads the archives to one host and runs the follow-up steps configured
+/// for it: remote extraction, post-upload hooks and retention.
+fn push_to_host(
+    server: &ServerConfig,
+    target: &upload::SshTarget,
+    remote_paths: &[String],
+    plan: &PushPlan,
+    progress: &TransferProgress,
+) -> Result<UploadOutcome> {
+    let max_retries = 3;
+    let files: Vec<(&str, &str)> = plan
+        .files
+        .iter()
+        .zip(remote_paths)
+        .map(|(file, remote_path)| (file.as_str(), remote_path.as_str()))
+        .collect();
+    let (session, outcomes) = upload::upload_many_with_progress(
+        target,
+        &files,
+        max_retries,
+        &upload_options(server, plan.keep_previous, plan.transfer)?,
+        progress,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to upload file: {} to {}",
+            plan.files.join(", "),
+            remote_paths.join(", ")
+        )
+    })?;
+    after_upload(&session, server, &files, plan, progress)?;
+
+    if outcomes.contains(&UploadOutcome::Uploaded) {
+        Ok(UploadOutcome::Uploaded)
+    } else {
+        Ok(UploadOutcome::Skipped)
+    }
+}
+
+/// Runs the steps that follow an upload of the `(local, remote)` files:
+/// remote extraction, post-upload hooks and retention.
+fn after_upload(
+    session: &Session,
+    server: &ServerConfig,
+    files: &[(&str, &str)],
+    plan: &PushPlan,
+    progress: &TransferProgress,
+) -> Result<()> {
+    let remote_dir = remote::expand_home(session, &server.remote_dir())?;
+
+    let post_upload = server.post_upload.clone().unwrap_or_default();
+    let mut uploaded = Vec::with_capacity(files.len());
+    for &(local_path, remote_path) in files {
+        let remote_path = &remote::expand_home(session, remote_path)?;
+        if let Some(extract_dir) = plan.extract_to {
+            progress.spin(&format!("Extracting archive into {}...", extract_dir));
+            let format = plan
+                .format
+                .ok_or_else(|| anyhow::anyhow!("{} is not an archive", local_path))?;
+            remote::extract_archive(session, remote_path, format, extract_dir)?;
+            if plan.remove_archive {
+                session
+                    .sftp()?
+                    .unlink(Path::new(remote_path))
+                    .with_context(|| format!("Failed to remove remote archive: {}", remote_path))?;
+            }
+            progress.println(&format!("✅ Extracted into {}", extract_dir));
+        }
+
+        if !post_upload.is_empty() {
+            progress.spin("Running post-upload hooks...");
+            let hook_context = HookContext {
+                remote_file: remote_path.to_string(),
+                remote_dir: remote_dir.clone(),
+                archive: local_path.to_string(),
+                format: plan.format.map_or("", |format| format.name()).to_string(),
+                ..HookContext::default()
+            };
+            run_hooks(session, &post_upload, &hook_context, progress)
+                .context("Post-upload hook failed")?;
+        }
 
-    #[arg(long)]
-    ip: Option<String>,
+        if let Some(name) = Path::new(remote_path).file_name() {
+            uploaded.push(name.to_string_lossy().into_owned());
+        }
+    }
 
-    #[arg(long)]
-    port: Option<u16>,
+    if let Some(policy) = &server.retention {
+        progress.spin("Applying retention policy...");
+        let retention_dir = remote::expand_home(session, server.retention_dir()?)?;
+        // The files just uploaded are never pruned
+        let protect: Vec<&str> = uploaded.iter().map(String::as_str).collect();
+        let removed = retention::prune(
+            &session.sftp()?,
+            &retention_dir,
+            server.remote_name_template(plan.remote_name),
+            policy,
+            &protect,
+            false,
+        )
+        .context("Failed to apply retention policy")?;
+        for line in pruned_lines(&removed, false) {
+            progress.println(&line);
+        }
+    }
+    Ok(())
+}
 
-    #[arg(long)]
-    username: Option<String>,
+/// Uploads to several hosts at once, at most `jobs` at a time, and prints a
+/// per-host summary.
+fn fan_out(
+    servers: &[ServerConfig],
+    targets: &[upload::SshTarget],
+    remote_paths: &[Vec<String>],
+    plan: &PushPlan,
+    jobs: usize,
+) -> Result<()> {
+    if servers.is_empty() {
+        return Ok(());
+    }
+    let multi = MultiProgress::new();
+    let progresses: Vec<TransferProgress> = servers
+        .iter()
+        .map(|server| {
+            let progress = TransferProgress::in_multi(&multi, &server.name);
+            progress.set_message("Queued");
+            progress
+        })
+        .collect();
+    let results: Vec<Mutex<Option<Result<UploadOutcome>>>> =
+        servers.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
 
-    #[arg(long)]
-    password: Option<String>,
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, servers.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= servers.len() {
+                    break;
+                }
+                let progress = &progresses[index];
+                let result = push_to_host(
+                    &servers[index],
+                    &targets[index],
+                    &remote_paths[index],
+                    plan,
+                    progress,
+                );
+                upload::finish_upload(
+                    progress,
+                    result.as_ref().ok().copied(),
+                    &targets[index],
+                    &remote_paths[index].join(", "),
+                );
+                *results[index].lock().unwrap() = Some(result);
+            });
+        }
+    });
 
-    #[arg(long)]
-    server: Option<String>,
+    println!("\nUpload summary:");
+    let mut failed = 0;
+    for (server, result) in servers.iter().zip(results) {
+        match result.into_inner().unwrap() {
+            Some(Ok(UploadOutcome::Uploaded)) => {
+                println!("  ✅ {} ({}): uploaded", server.name, server.ip)
+            }
+            Some(Ok(UploadOutcome::Skipped)) => {
+                println!("  ✅ {} ({}): already up to date", server.name, server.ip)
+            }
+            Some(Err(e)) => {
+                failed += 1;
+                println!("  ❌ {} ({}): {:#}", server.name, server.ip, e);
+            }
+            None => {
+                failed += 1;
+                println!("  ❌ {} ({}): not attempted", server.name, server.ip);
+            }
+        }
+    }
 
-    #[arg(long, action)]
-    init_config: bool,
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} uploads failed",
+            failed,
+            servers.len()
+        ));
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+fn run_deploy(args: DeployArgs, config: Option<Config>) -> Result<()> {
+    let servers = resolve_servers(args.connection, config)?;
+    let format = effective_format(args.format, &servers, CompressionFormat::TarGz)?;
+    let health_checks = servers
+        .iter()
+        .map(|server| health_check(server, &args.health))
+        .collect::<Result<Vec<_>>>()?;
 
-    let args = Args::parse();
+    let release = deploy::release_name();
+    let remote_paths: Vec<String> = servers
+        .iter()
+        .map(|server| ReleaseLayout::new(&server.remote_dir()).release_archive(&release, format))
+        .collect();
 
-    if args.init_config {
-        Config::create_example()?;
-        return Ok(());
+    // Passwords are prompted for up front so the batches can run unattended
+    let targets: Vec<upload::SshTarget> = servers.iter().map(ssh_target).collect();
+
+    let archive = LocalArchive::create(&args.archive, &archive_name(&args.path, format)?)?;
+    let output_path = archive.path.clone();
+
+    let hook_env = hook_env(
+        &args.path,
+        &output_path,
+        Some(format),
+        &servers,
+        &remote_paths,
+    );
+    let (pre_compress, pre_upload) = local_hooks(&servers, args.local_hooks);
+    prepare_archive(
+        &[PathBuf::from(&args.path)],
+        &output_path,
+        format,
+        args.verify_archive,
+        &hook_env,
+        &pre_compress,
+        &pre_upload,
+    )
+    .inspect_err(|_| archive.discard())?;
+    archive.compressed();
+
+    let plan = DeployPlan {
+        output_path: &output_path,
+        format,
+        release: &release,
+        transfer: &args.transfer,
+    };
+    let hosts: Vec<DeployHost> = servers
+        .iter()
+        .zip(&targets)
+        .zip(&health_checks)
+        .map(|((server, target), health)| DeployHost {
+            server,
+            target,
+            health,
+        })
+        .collect();
+    let deployed = rolling_deploy(&hosts, &plan, args.batch_size);
+    archive.finish(deployed.is_ok());
+    let deployed = deployed?;
+
+    let keep_releases = |server: &ServerConfig| {
+        args.keep_releases
+            .or(server.keep_releases)
+            .unwrap_or(deploy::DEFAULT_KEEP_RELEASES)
+    };
+    let cleanup_spinner = LoadingSpinner::new("Removing old releases...");
+    let mut removed = 0;
+    // Only once every host is live, the previous releases are rollback targets until then
+    for (host, deployed_host) in hosts.iter().zip(&deployed) {
+        removed += deploy::remove_old_releases(
+            &deployed_host.session,
+            &deployed_host.layout,
+            keep_releases(host.server),
+        )
+        .inspect_err(|_| cleanup_spinner.finish_with_error("Failed to remove old releases"))?
+        .len();
     }
+    cleanup_spinner.finish_with_success(&format!(
+        "Release {} is live ({} old release(s) removed)",
+        release, removed
+    ));
+    Ok(())
+}
 
-    let config = Config::load().unwrap_or_else(|e| {
-        warn!(
-            "Failed to load config: {}. Use --init-config to create one.",
-            e
-        );
-        None
-    });
+/// The release shared by all hosts of a deploy.
+struct DeployPlan<'a> {
+    output_path: &'a str,
+    format: CompressionFormat,
+    release: &'a str,
+    transfer: &'a TransferArgs,
+}
+
+/// One host of a (rolling) deploy.
+struct DeployHost<'a> {
+    server: &'a ServerConfig,
+    target: &'a upload::SshTarget,
+    health: &'a HealthCheck,
+}
+
+/// A host whose `current` symlink was switched to the new release.
+struct DeployedHost {
+    session: Session,
+    layout: ReleaseLayout,
+    /// Release `current` points at now, `plan.release` unless that was taken.
+    release: String,
+    /// Release `current` pointed at before, the rollback target.
+    previous: Option<String>,
+}
+
+/// Deploys to `batch_size` hosts at a time. Each batch must be live and
+/// healthy before the next one starts; when a host of a batch fails, the
+/// remaining hosts are skipped and every host switched so far is rolled back
+/// to its previous release.
+fn rolling_deploy(
+    hosts: &[DeployHost],
+    plan: &DeployPlan,
+    batch_size: usize,
+) -> Result<Vec<DeployedHost>> {
+    let multi = MultiProgress::new();
+    let progresses: Vec<TransferProgress> = if hosts.len() == 1 {
+        vec![TransferProgress::new()]
+    } else {
+        hosts
+            .iter()
+            .map(|host| {
+                let progress = TransferProgress::in_multi(&multi, &host.server.name);
+                progress.set_message("Queued");
+                progress
+            })
+            .collect()
+    };
+    let results: Vec<Mutex<Option<Result<()>>>> = hosts.iter().map(|_| Mutex::new(None)).collect();
+    let deployed: Vec<Mutex<Option<DeployedHost>>> =
+        hosts.iter().map(|_| Mutex::new(None)).collect();
 
-    let (ip, username, mut password, port, remote_path_template) =
-        if let Some(server_name) = &args.server {
-            match &config {
-                Some(cfg) => {
-                    if let Some(server) = cfg.servers.get(server_name) {
-                        info!("Using configured server: {}", server.name);
-                        (
-                            server.ip.clone(),
-                            server.username.clone(),
-                            server.password.clone().unwrap_or_default(),
-                            server.port.unwrap_or(22),
-                            server
-                                .remote_path
-                                .clone()
-                                .unwrap_or_else(|| format!("/home/{}", server.username)),
-                        )
-                    } else {
-                        error!("Server '{}' not found in config.", server_name);
-                        return Err(anyhow::anyhow!(
-                            "Server '{}' not found in config.",
-                            server_name
-                        ));
+    let indices: Vec<usize> = (0..hosts.len()).collect();
+    for batch in indices.chunks(batch_size.max(1)) {
+        thread::scope(|scope| {
+            for &index in batch {
+                let (progresses, results, deployed) = (&progresses, &results, &deployed);
+                scope.spawn(move || {
+                    let host = &hosts[index];
+                    let progress = &progresses[index];
+                    let result = deploy_to_host(host, plan, progress).and_then(|deployed_host| {
+                        let verified = verify_host(host, &deployed_host, plan, progress);
+                        let release = deployed_host.release.clone();
+                        *deployed[index].lock().unwrap() = Some(deployed_host);
+                        verified.map(|()| release)
+                    });
+                    match &result {
+                        Ok(release) => {
+                            progress.finish_with_success(&format!("Release {} is live", release))
+                        }
+                        Err(_) => progress.finish_with_error("Deploy failed"),
                     }
-                }
-                None => {
-                    error!("No configuration loaded. Use --init-config to create one.");
-                    return Err(anyhow::anyhow!("No configuration loaded."));
-                }
+                    *results[index].lock().unwrap() = Some(result.map(|_| ()));
+                });
             }
-        } else if let Some(configured_server) = input::get_server_config(config) {
-            let username = configured_server.username.clone();
-            let remote_path = configured_server
-                .remote_path
-                .unwrap_or_else(|| format!("/home/{}", username));
-            (
-                configured_server.ip,
-                username,
-                configured_server.password.unwrap_or_default(),
-                configured_server.port.unwrap_or(22),
-                remote_path,
-            )
+        });
+
+        let batch_failed = batch
+            .iter()
+            .any(|&index| matches!(*results[index].lock().unwrap(), Some(Err(_))));
+        if batch_failed {
+            break;
+        }
+    }
+
+    let results: Vec<Option<Result<()>>> = results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap())
+        .collect();
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result, Some(Err(_))))
+        .count();
+
+    let deployed: Vec<Option<DeployedHost>> = deployed
+        .into_iter()
+        .map(|deployed_host| deployed_host.into_inner().unwrap())
+        .collect();
+
+    // Roll back everything that was switched, so all hosts serve one release
+    let mut rollbacks: Vec<Option<Result<String>>> = hosts.iter().map(|_| None).collect();
+    if failed > 0 {
+        for (index, deployed_host) in deployed.iter().enumerate() {
+            if let Some(deployed_host) = deployed_host {
+                rollbacks[index] = Some(roll_back_host(
+                    &hosts[index],
+                    deployed_host,
+                    &progresses[index],
+                ));
+            }
+        }
+    }
+
+    if hosts.len() > 1 {
+        println!("\nDeploy summary (release {}):", plan.release);
+    }
+    for ((host, result), rollback) in hosts.iter().zip(&results).zip(&rollbacks) {
+        let name = format!("{} ({})", host.server.name, host.server.ip);
+        if hosts.len() > 1 {
+            match result {
+                Some(Ok(())) if rollback.is_none() => println!("  ✅ {}: live", name),
+                Some(Ok(())) => println!("  ✅ {}: deployed", name),
+                Some(Err(e)) => println!("  ❌ {}: {:#}", name, e),
+                None => println!("  ⏭️  {}: skipped", name),
+            }
+        }
+        match rollback {
+            Some(Ok(release)) => println!("  ↩️  {}: rolled back to {}", name, release),
+            Some(Err(e)) => println!("  ❌ {}: rollback failed: {:#}", name, e),
+            None => {}
+        }
+    }
+
+    if failed > 0 {
+        let skipped = results.iter().filter(|result| result.is_none()).count();
+        let rolled_back = rollbacks
+            .iter()
+            .filter(|rollback| matches!(rollback, Some(Ok(_))))
+            .count();
+        return Err(anyhow::anyhow!(
+            "Deploy halted: {} of {} hosts failed, {} skipped, {} rolled back",
+            failed,
+            hosts.len(),
+            skipped,
+            rolled_back
+        ));
+    }
+    Ok(deployed.into_iter().flatten().collect())
+}
+
+/// Uploads the release archive to one host, extracts it and switches
+/// `current` to it.
+fn deploy_to_host(
+    host: &DeployHost,
+    plan: &DeployPlan,
+    progress: &TransferProgress,
+) -> Result<DeployedHost> {
+    let remote_path =
+        ReleaseLayout::new(&host.server.remote_dir()).release_archive(plan.release, plan.format);
+
+    let max_retries = 3;
+    let (session, _) = upload::upload_with_progress(
+        host.target,
+        plan.output_path,
+        &remote_path,
+        max_retries,
+        &upload_options(host.server, false, plan.transfer)?,
+        progress,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to upload file: {} to {}",
+            plan.output_path, remote_path
+        )
+    })?;
+
+    let layout = ReleaseLayout::new(&remote::expand_home(&session, &host.server.remote_dir())?);
+    let remote_path = layout.release_archive(plan.release, plan.format);
+
+    progress.spin(&format!("Activating release {}...", plan.release));
+    let previous = deploy::current_release(&session, &layout)?;
+    let release =
+        deploy::activate_release(&session, &layout, plan.release, &remote_path, plan.format)
+            .context("Release activation failed")?;
+    if release != plan.release {
+        progress.println(&format!(
+            "Release {} already exists, deployed as {}",
+            plan.release, release
+        ));
+    }
+
+    Ok(DeployedHost {
+        session,
+        layout,
+        release,
+        previous,
+    })
+}
+
+/// Runs the `post_deploy` hooks on a freshly switched host and waits for it
+/// to pass its health check.
+fn verify_host(
+    host: &DeployHost,
+    deployed: &DeployedHost,
+    plan: &DeployPlan,
+    progress: &TransferProgress,
+) -> Result<()> {
+    let post_deploy = host.server.post_deploy.clone().unwrap_or_default();
+    if !post_deploy.is_empty() {
+        progress.spin("Running post-deploy hooks...");
+        let hook_context = HookContext {
+            archive: plan.output_path.to_string(),
+            format: plan.format.name().to_string(),
+            ..release_hook_context(&deployed.layout, &deployed.release)
+        };
+        run_hooks(&deployed.session, &post_deploy, &hook_context, progress)
+            .context("Post-deploy hook failed")?;
+    }
+
+    if host.health.is_configured() {
+        progress.spin("Waiting for health check...");
+        host.health
+            .wait(&deployed.session)
+            .context("Health check failed")?;
+    }
+    Ok(())
+}
+
+/// Points `current` of a deployed host back at the release it served before
+/// and reruns the `post_deploy` hooks for it.
+fn roll_back_host(
+    host: &DeployHost,
+    deployed: &DeployedHost,
+    progress: &TransferProgress,
+) -> Result<String> {
+    let previous = deployed
+        .previous
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No earlier release to roll back to"))?;
+    deploy::switch_current(&deployed.session, &deployed.layout, &previous)?;
+    let post_deploy = host.server.post_deploy.clone().unwrap_or_default();
+    run_hooks(
+        &deployed.session,
+        &post_deploy,
+        &release_hook_context(&deployed.layout, &previous),
+        progress,
+    )
+    .context("Post-deploy hook failed")?;
+    Ok(previous)
+}
+
+fn run_rollback(args: RollbackArgs, config: Option<Config>) -> Result<()> {
+    let server = resolve_server(args.connection, config)?;
+    let target = ssh_target(&server);
+    let session = upload::connect(&target)?;
+    let layout = ReleaseLayout::new(&remote::expand_home(&session, &server.remote_dir())?);
+
+    let releases = deploy::list_releases(&session, &layout)?;
+    let current = deploy::current_release(&session, &layout)?;
+    println!("Releases in {}:", layout.releases_dir());
+    for release in &releases {
+        let marker = if Some(release) == current.as_ref() {
+            " (current)"
         } else {
-            let ip = args
-                .ip
-                .unwrap_or_else(|| input::prompt_ip_address("Server IP: "));
-            let username = args
-                .username
-                .unwrap_or_else(|| input::prompt_string("Username: "));
-            let password = args
-                .password
-                .unwrap_or_else(|| input::prompt_secret("Password: "));
-            let remote_path = format!("/home/{}", username);
-            (ip, username, password, 22, remote_path)
+            ""
         };
+        println!("  {}{}", release, marker);
+    }
 
-    if password.is_empty() {
-        password = input::prompt_secret("Password: ");
+    let release = deploy::rollback_target(&releases, current.as_deref(), args.to.as_deref())?;
+    let rollback_spinner = LoadingSpinner::new(&format!("Rolling back to {}...", release));
+    if let Err(e) = deploy::switch_current(&session, &layout, &release) {
+        rollback_spinner.finish_with_error("Rollback failed");
+        return Err(e);
     }
+    rollback_spinner.finish_with_success(&format!("Release {} is live", release));
 
-    let input_path = match args.path {
-        Some(path) => path,
-        None => {
-            error!("Path is required when not initializing config");
-            return Err(anyhow::anyhow!("Path is required"));
+    run_post_deploy(&session, &server, &release_hook_context(&layout, &release))
+}
+
+fn run_prune(args: PruneArgs, config: Option<Config>) -> Result<()> {
+    let server = resolve_server(args.connection, config)?;
+    let policy = server.retention.clone().ok_or_else(|| {
+        anyhow::anyhow!(
+            "Server '{}' has no retention policy configured",
+            server.name
+        )
+    })?;
+    let target = ssh_target(&server);
+    let session = upload::connect(&target)?;
+
+    let removed = retention::prune(
+        &session.sftp()?,
+        &remote::expand_home(&session, server.retention_dir()?)?,
+        server.remote_name.as_deref(),
+        &policy,
+        &[],
+        args.dry_run,
+    )?;
+    for line in pruned_lines(&removed, args.dry_run) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn run_pull(args: PullArgs, config: Option<Config>) -> Result<()> {
+    let server = resolve_server(args.connection, config)?;
+    let target = ssh_target(&server);
+    let options = DownloadOptions {
+        buffer_size: args.buffer_size,
+        checksum: args.checksum.or(server.checksum).unwrap_or_default(),
+    };
+
+    let session = upload::connect(&target)?;
+    let sftp = session.sftp()?;
+    let remote_path = remote::expand_home(&session, &args.remote_path)?;
+    let remote_path = remote_path.as_str();
+    let stat = sftp
+        .stat(Path::new(remote_path))
+        .with_context(|| format!("Remote path not found: {}", remote_path))?;
+    let remote_name = Path::new(remote_path.trim_end_matches('/'))
+        .file_name()
+        .map_or_else(
+            || "root".to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+
+    let max_retries = 3;
+    let progress = TransferProgress::new();
+    let output_path = if stat.is_dir() {
+        let format = args.format.unwrap_or(CompressionFormat::TarGz);
+        let output_path = args
+            .output
+            .unwrap_or_else(|| format!("{}.{}", remote_name, format.extension()));
+        refuse_existing(&output_path)?;
+
+        progress.spin(&format!("Archiving {} on the server...", remote_path));
+        match download::create_remote_archive(&session, remote_path, format) {
+            Ok(archive) => {
+                if let Some(warnings) = &archive.warnings {
+                    progress.println(&format!("⚠️  tar reported: {}", warnings));
+                }
+                let result = download::download_with_retry(
+                    &target,
+                    &archive.path,
+                    Path::new(&output_path),
+                    max_retries,
+                    &options,
+                    &progress,
+                );
+                let _ = sftp.unlink(Path::new(&archive.path));
+                result
+            }
+            Err(e) => {
+                progress.println(&format!(
+                    "⚠️  Cannot archive on the server ({:#}), copying over SFTP instead",
+                    e
+                ));
+                mirror_and_compress(
+                    &session,
+                    &sftp,
+                    remote_path,
+                    &output_path,
+                    format,
+                    &options,
+                    &progress,
+                )
+            }
         }
+        .inspect_err(|_| progress.finish_with_error("Download failed"))?;
+        output_path
+    } else {
+        let output_path = args.output.unwrap_or(remote_name);
+        refuse_existing(&output_path)?;
+        download::download_with_retry(
+            &target,
+            remote_path,
+            Path::new(&output_path),
+            max_retries,
+            &options,
+            &progress,
+        )
+        .inspect_err(|_| progress.finish_with_error("Download failed"))?;
+        output_path
     };
+    progress.finish_with_success(&format!(
+        "Downloaded ({}) {} to {}",
+        server.ip, remote_path, output_path
+    ));
 
-    let format = args.format.unwrap_or_default();
+    if let Some(extract_dir) = args.extract_to {
+        let archive = Path::new(&output_path);
+        extract_with_progress(
+            archive,
+            archive_format(archive, None)?,
+            Path::new(&extract_dir),
+        )?;
+    }
+    Ok(())
+}
 
-    let output_path = format!(
-        "{}.{}",
-        Path::new(&input_path)
-            .file_name()
-            .unwrap()
-            .to_string_lossy(),
-        format.extension()
+fn run_extract(args: ExtractArgs) -> Result<()> {
+    let archive = Path::new(&args.archive);
+    let format = archive_format(archive, args.format)?;
+    extract_with_progress(archive, format, Path::new(&args.directory))
+}
+
+fn extract_with_progress(archive: &Path, format: CompressionFormat, dest_dir: &Path) -> Result<()> {
+    let progress = TransferProgress::new();
+    let summary = extract::extract_archive(archive, format, dest_dir, &progress)
+        .inspect_err(|_| progress.finish_with_error("Extraction failed"))?;
+    progress.finish_with_success(&format!(
+        "Extracted {} files, {} directories ({}) into {}",
+        summary.files,
+        summary.dirs,
+        HumanBytes(summary.bytes),
+        dest_dir.display()
+    ));
+    Ok(())
+}
+
+fn run_list(args: ListArgs) -> Result<()> {
+    let archive = Path::new(&args.archive);
+    let format = archive_format(archive, args.format)?;
+    let entries = extract::list_archive(archive, format)?;
+
+    let mut files = 0;
+    let mut total = 0;
+    for entry in &entries {
+        println!("{}", entry_line(entry));
+        if entry.kind == EntryKind::File {
+            files += 1;
+            total += entry.size;
+        }
+    }
+    println!(
+        "\n{} entries, {} files, {} uncompressed",
+        entries.len(),
+        files,
+        HumanBytes(total)
+    );
+    Ok(())
+}
+
+/// Line of `flash list` output in the style of `ls -l`.
+fn entry_line(entry: &ArchiveEntry) -> String {
+    let kind = match entry.kind {
+        EntryKind::File => '-',
+        EntryKind::Dir => 'd',
+        EntryKind::Symlink => 'l',
+        EntryKind::Other => '?',
+    };
+    let mode = entry
+        .mode
+        .map_or_else(|| "---------".to_string(), permission_string);
+    let mut line = format!(
+        "{}{} {:>10}  {}",
+        kind,
+        mode,
+        HumanBytes(entry.size).to_string(),
+        entry.path.display()
     );
-    let remote_path = format!("{}/{}", remote_path_template, output_path);
+    if let Some(target) = &entry.link_target {
+        line.push_str(&format!(" -> {}", target.display()));
+    }
+    line
+}
+
+fn permission_string(mode: u32) -> String {
+    (0..9)
+        .map(|bit| {
+            if mode & (0o400 >> bit) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect()
+}
+
+fn run_verify(args: VerifyArgs) -> Result<()> {
+    let archive = Path::new(&args.archive);
+    let format = archive_format(archive, args.format)?;
+    let sources: Vec<PathBuf> = args.source.iter().map(PathBuf::from).collect();
+    verify_with_progress(archive, format, &sources)
+}
+
+/// Decodes `archive` completely and, given the `sources` it was created from,
+/// compares the entries with the source trees.
+fn verify_with_progress(
+    archive: &Path,
+    format: CompressionFormat,
+    sources: &[PathBuf],
+) -> Result<()> {
+    let progress = TransferProgress::new();
+    progress.set_message(&format!("Verifying {}...", archive.display()));
+    let listing = verify::verify_archive(archive, format, &progress)
+        .inspect_err(|_| progress.finish_with_error("Archive is corrupt"))?;
+
+    if !sources.is_empty() {
+        progress.spin("Comparing with the source...");
+        let source_listing = match sources {
+            [source] => verify::source_listing(source)?,
+            _ => verify::bundle_listing(sources, &sources::entry_names(sources)?)?,
+        };
+        let diff = verify::compare_listings(&listing, &source_listing);
+        if !diff.is_empty() {
+            progress.finish_with_error("Archive does not match its source");
+            return Err(anyhow::anyhow!(
+                "{} does not match {}:\n{}",
+                archive.display(),
+                display_paths(sources),
+                diff.describe()
+            ));
+        }
+    }
+    progress.finish_with_success(&format!(
+        "Verified {} ({} entries)",
+        archive.display(),
+        listing.len()
+    ));
+    Ok(())
+}
+
+/// `format`, or the format given by the extension of `archive`.
+fn archive_format(archive: &Path, format: Option<CompressionFormat>) -> Result<CompressionFormat> {
+    format
+        .or_else(|| CompressionFormat::from_path(archive))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Cannot tell the format of {}, pass --format",
+                archive.display()
+            )
+        })
+}
+
+/// Fallback for servers without `tar` or exec access: copies `remote_dir`
+/// file by file over SFTP and compresses the copy locally.
+fn mirror_and_compress(
+    session: &Session,
+    sftp: &ssh2::Sftp,
+    remote_dir: &str,
+    output_path: &str,
+    format: CompressionFormat,
+    options: &DownloadOptions,
+    progress: &TransferProgress,
+) -> Result<()> {
+    cleanup::install_interrupt_handler();
+    let staging = cleanup::unique_temp_dir()?;
+    let remote_name = Path::new(remote_dir.trim_end_matches('/'))
+        .file_name()
+        .map_or_else(|| "root".into(), |name| name.to_os_string());
+    let copy = staging.join(remote_name);
+
+    progress.spin(&format!("Copying {} over SFTP...", remote_dir));
+    let result = download::mirror_dir(
+        session,
+        sftp,
+        Path::new(remote_dir),
+        &copy,
+        options,
+        progress,
+    )
+    .and_then(|_| {
+        progress.set_message(&format!(
+            "Compressing with {} format...",
+            format.description()
+        ));
+        // Compressing the staging dir keeps the directory name in the archive,
+        // like the remote tar does
+        compress::compress(&staging.to_string_lossy(), output_path, format)
+    });
+    if let Err(e) = cleanup::remove(&staging) {
+        warn!("{:#}", e);
+    }
+    result
+}
+
+/// Fails if `path` exists, so downloads never replace local files.
+fn refuse_existing(path: &str) -> Result<()> {
+    if Path::new(path).exists() {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --output to save elsewhere",
+            path
+        ));
+    }
+    Ok(())
+}
+
+fn pruned_lines(removed: &[RemoteArchive], dry_run: bool) -> Vec<String> {
+    if removed.is_empty() {
+        return vec!["No archives to prune.".to_string()];
+    }
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    removed
+        .iter()
+        .map(|archive| format!("{} {} ({})", verb, archive.name, HumanBytes(archive.size)))
+        .collect()
+}
+
+fn release_hook_context(layout: &ReleaseLayout, release: &str) -> HookContext {
+    HookContext {
+        remote_file: layout.current_link(),
+        remote_dir: layout.root.clone(),
+        release: release.to_string(),
+        release_dir: layout.release_dir(release),
+        ..HookContext::default()
+    }
+}
+
+/// Runs remote hooks, streaming their output to the terminal unless other
+/// hosts share it, in which case the output is printed line by line.
+fn run_hooks(
+    session: &Session,
+    hooks: &[String],
+    context: &HookContext,
+    progress: &TransferProgress,
+) -> Result<()> {
+    if progress.is_shared() {
+        hooks::run_remote_hooks_logged(session, hooks, context, |line| progress.println(line))
+    } else {
+        progress.suspend(|| hooks::run_remote_hooks(session, hooks, context))
+    }
+}
+
+fn run_post_deploy(session: &Session, server: &ServerConfig, context: &HookContext) -> Result<()> {
+    let post_deploy = server.post_deploy.clone().unwrap_or_default();
+    hooks::run_remote_hooks(session, &post_deploy, context).context("Post-deploy hook failed")
+}
+
+/// Fails before anything is uploaded if a server would prune its default remote directory.
+fn check_retention_dirs(servers: &[ServerConfig]) -> Result<()> {
+    for server in servers.iter().filter(|server| server.retention.is_some()) {
+        server.retention_dir()?;
+    }
+    Ok(())
+}
+
+/// Picks the servers to talk to: the servers and groups named with `--server`,
+/// the default or interactively selected one(s), or finally one entered by hand.
+fn resolve_servers(
+    connection: ConnectionArgs,
+    config: Option<Config>,
+) -> Result<Vec<ServerConfig>> {
+    if !connection.server.is_empty() {
+        let mut servers: Vec<ServerConfig> = Vec::new();
+        for name in &connection.server {
+            for server in lookup_servers(config.as_ref(), name)? {
+                // A server may be named directly and through a group
+                if !servers
+                    .iter()
+                    .any(|s| s.name == server.name && s.ip == server.ip)
+                {
+                    servers.push(server);
+                }
+            }
+        }
+        return Ok(servers);
+    }
+
+    if let Some(configured_servers) = input::get_server_config(config) {
+        return Ok(configured_servers);
+    }
+
+    let ip = connection
+        .ip
+        .unwrap_or_else(|| input::prompt_ip_address("Server IP: "));
+    let username = connection
+        .username
+        .unwrap_or_else(|| input::prompt_string("Username: "));
+    let password = connection
+        .password
+        .unwrap_or_else(|| input::prompt_secret("Password: "));
+    Ok(vec![ServerConfig {
+        name: ip.clone(),
+        ip,
+        username,
+        password: Some(password),
+        port: connection.port,
+        ..ServerConfig::default()
+    }])
+}
+
+/// Like [`resolve_servers`], for commands that work on exactly one server.
+fn resolve_server(connection: ConnectionArgs, config: Option<Config>) -> Result<ServerConfig> {
+    let mut servers = resolve_servers(connection, config)?;
+    if servers.len() != 1 {
+        let names: Vec<&str> = servers.iter().map(|s| s.name.as_str()).collect();
+        return Err(anyhow::anyhow!(
+            "This command works on a single server, got: {}",
+            names.join(", ")
+        ));
+    }
+    Ok(servers.remove(0))
+}
+
+fn lookup_servers(config: Option<&Config>, name: &str) -> Result<Vec<ServerConfig>> {
+    match config {
+        Some(cfg) => {
+            let servers = cfg.resolve(name).inspect_err(|e| error!("{}", e))?;
+            for server in &servers {
+                info!("Using configured server: {}", server.name);
+            }
+            Ok(servers)
+        }
+        None => {
+            error!("No configuration loaded. Run `flash config init` to create one.");
+            Err(anyhow::anyhow!("No configuration loaded."))
+        }
+    }
+}
+
+/// Health check for `server`, with the command line taking precedence over
+/// the configuration.
+fn health_check(server: &ServerConfig, cli: &HealthArgs) -> Result<HealthCheck> {
+    let timeout = match cli.timeout.as_ref().or(server.health_timeout.as_ref()) {
+        Some(timeout) => Duration::from_secs(units::parse_duration(timeout)?),
+        None => health::DEFAULT_TIMEOUT,
+    };
+    Ok(HealthCheck {
+        command: cli.command.clone().or_else(|| server.health_check.clone()),
+        url: cli
+            .url
+            .as_ref()
+            .or(server.health_url.as_ref())
+            .map(|url| health::health_url(url, &server.ip)),
+        timeout,
+        ..HealthCheck::default()
+    })
+}
+
+/// Format from the command line, otherwise the one the servers agree on,
+/// otherwise `fallback`.
+fn effective_format(
+    cli: Option<CompressionFormat>,
+    servers: &[ServerConfig],
+    fallback: CompressionFormat,
+) -> Result<CompressionFormat> {
+    if let Some(format) = cli {
+        return Ok(format);
+    }
+    let mut configured = servers.iter().filter_map(|server| server.format);
+    match configured.next() {
+        None => Ok(fallback),
+        Some(first) if configured.all(|format| format == first) => Ok(first),
+        Some(_) => Err(anyhow::anyhow!(
+            "The selected servers are configured with different formats, pass --format"
+        )),
+    }
+}
+
+fn ssh_target(server: &ServerConfig) -> upload::SshTarget {
+    let mut password = server.password.clone().unwrap_or_default();
+    if password.is_empty() {
+        password = input::prompt_secret("Password: ");
+    }
+    upload::SshTarget {
+        ip: server.ip.clone(),
+        port: server.port(),
+        username: server.username.clone(),
+        password,
+    }
+}
+
+fn upload_options(
+    server: &ServerConfig,
+    keep_previous: bool,
+    transfer: &TransferArgs,
+) -> Result<upload::UploadOptions> {
+    let defaults = upload::UploadOptions::default();
+    let limit = match (transfer.limit_rate, &server.bandwidth_limit) {
+        (Some(rate), _) => rate,
+        (None, Some(limit)) => throttle::parse_rate(limit)
+            .with_context(|| format!("Invalid bandwidth_limit of server '{}'", server.name))?,
+        (None, None) => 0,
+    };
+    let throttle = match &transfer.limit_rate_file {
+        Some(path) => Some(Throttle::with_control_file(limit, path)),
+        None if limit > 0 => Some(Throttle::new(limit)),
+        None => None,
+    };
+    Ok(upload::UploadOptions {
+        keep_previous: keep_previous || server.keep_previous.unwrap_or(false),
+        dir_mode: server
+            .dir_mode
+            .map_or(defaults.dir_mode, |mode| mode as i32),
+        file_mode: server
+            .file_mode
+            .map_or(defaults.file_mode, |mode| mode as i32),
+        uid: server.uid,
+        gid: server.gid,
+        connections: transfer.connections.max(1),
+        buffer_size: transfer.buffer_size,
+        throttle,
+        checksum: transfer.checksum.or(server.checksum).unwrap_or_default(),
+    })
+}
+
+/// Local archive name for `input_path`, `<basename>.<ext>`, naming `.` and
+/// `..` after the directory they resolve to.
+fn archive_name(input_path: &str, format: CompressionFormat) -> Result<String> {
+    let name = sources::file_name(Path::new(input_path))?;
+    Ok(format!("{}.{}", name, format.extension()))
+}
+
+/// Remote path of the upload on each server. The file is named by the
+/// `--remote-name` template, else by the server's `remote_name`, else
+/// `default_name`.
+fn remote_paths_for(
+    servers: &[ServerConfig],
+    template: Option<&str>,
+    context: &RemoteNameContext,
+    default_name: &str,
+) -> Result<Vec<String>> {
+    servers
+        .iter()
+        .map(|server| {
+            let name = match server.remote_name_template(template) {
+                Some(template) => context.remote_name(template)?,
+                None => default_name.to_string(),
+            };
+            Ok(format!("{}/{}", server.remote_dir(), name))
+        })
+        .collect()
+}
+
+/// Environment handed to local hooks. With several servers, the per-server
+/// values are joined with commas.
+fn hook_env(
+    input_path: &str,
+    output_path: &str,
+    format: Option<CompressionFormat>,
+    servers: &[ServerConfig],
+    remote_paths: &[String],
+) -> Vec<(&'static str, String)> {
+    let join = |values: Vec<&str>| values.join(",");
+    vec![
+        ("FLASH_SOURCE", input_path.to_string()),
+        ("FLASH_ARCHIVE", output_path.to_string()),
+        (
+            "FLASH_FORMAT",
+            format.map_or("", |format| format.name()).to_string(),
+        ),
+        (
+            "FLASH_SERVER",
+            join(servers.iter().map(|s| s.name.as_str()).collect()),
+        ),
+        (
+            "FLASH_HOST",
+            join(servers.iter().map(|s| s.ip.as_str()).collect()),
+        ),
+        (
+            "FLASH_REMOTE_PATH",
+            join(remote_paths.iter().map(String::as_str).collect()),
+        ),
+    ]
+}
+
+/// Local hooks to run: those configured for the servers, each distinct command
+/// once, followed by the ones given on the command line.
+fn local_hooks(servers: &[ServerConfig], cli: LocalHookArgs) -> (Vec<String>, Vec<String>) {
+    let collect = |configured: Vec<&Vec<String>>, extra: Vec<String>| {
+        let mut hooks: Vec<String> = Vec::new();
+        for hook in configured.into_iter().flatten() {
+            if !hooks.contains(hook) {
+                hooks.push(hook.clone());
+            }
+        }
+        hooks.extend(extra);
+        hooks
+    };
+    (
+        collect(
+            servers
+                .iter()
+                .filter_map(|s| s.pre_compress.as_ref())
+                .collect(),
+            cli.pre_compress,
+        ),
+        collect(
+            servers
+                .iter()
+                .filter_map(|s| s.pre_upload.as_ref())
+                .collect(),
+            cli.pre_upload,
+        ),
+    )
+}
+
+/// Runs the local `pre_compress` hooks, compresses `input_path` into
+/// `output_path`, verifies the archive if asked to and runs the `pre_upload`
+/// hooks.
+fn prepare_archive(
+    inputs: &[PathBuf],
+    output_path: &str,
+    format: CompressionFormat,
+    verify_archive: bool,
+    hook_env: &[(&str, String)],
+    pre_compress: &[String],
+    pre_upload: &[String],
+) -> Result<()> {
+    hooks::run_local_hooks(pre_compress, hook_env).context("Pre-compress hook failed")?;
 
     let compress_spinner = LoadingSpinner::new("Starting compression...");
     compress_spinner.update_message(&format!(
         "Compressing with {} format...",
         format.description()
     ));
-    if let Err(e) = compress::compress(&input_path, &output_path, format)
-        .with_context(|| format!("Failed to compress {}: {}", input_path, output_path))
-    {
+    let compressed = match inputs {
+        [input] => compress::compress(&input.to_string_lossy(), output_path, format),
+        _ => sources::entry_names(inputs)
+            .and_then(|names| compress::compress_bundle(inputs, &names, output_path, format)),
+    };
+    if let Err(e) = compressed.with_context(|| {
+        format!(
+            "Failed to compress {}: {}",
+            display_paths(inputs),
+            output_path
+        )
+    }) {
         compress_spinner.finish_with_error("Compression failed");
         return Err(e);
     }
     compress_spinner.finish_with_success("Compressed successfully");
 
-    let upload_spinner = LoadingSpinner::new("Preparing upload...");
-    let max_retries = 3;
-    if Path::new(&output_path).exists() {
-        drop(upload_spinner);
-        upload::upload_via_sftp_with_retry(
-            &ip,
-            port,
-            &username,
-            &password,
-            &output_path,
-            &remote_path,
-            max_retries,
-        )
-        .with_context(|| format!("Failed to upload file: {} to {}", output_path, remote_path))?;
-    } else {
-        upload_spinner.finish_with_error("Local file does not exist");
-        return Err(anyhow::anyhow!(
-            "Local file does not exist: {}",
-            output_path
-        ));
+    if verify_archive {
+        verify_with_progress(Path::new(output_path), format, inputs)?;
     }
 
+    hooks::run_local_hooks(pre_upload, hook_env).context("Pre-upload hook failed")?;
+
     Ok(())
 }
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,228 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ssh2::{Channel, Session};
+
+use crate::compress::CompressionFormat;
+
+/// Captured result of a command run with [`exec_capture`].
+#[derive(Debug, Clone)]
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: i32,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == 0
+    }
+}
+
+/// Runs `command` on the server, streaming its stdout and stderr to the local
+/// ones as it runs, and returns the remote exit status.
+pub fn exec_streaming(session: &Session, command: &str) -> Result<i32> {
+    exec_with(session, command, None, &mut io::stdout(), &mut io::stderr())
+}
+
+/// Runs `command` on the server and collects its output.
+pub fn exec_capture(session: &Session, command: &str) -> Result<ExecOutput> {
+    capture(session, command, None)
+}
+
+/// Like [`exec_capture`], failing once `command` has run for `timeout`
+/// without finishing. The command is then abandoned, not killed.
+pub fn exec_capture_timeout(
+    session: &Session,
+    command: &str,
+    timeout: Duration,
+) -> Result<ExecOutput> {
+    capture(session, command, Some(Instant::now() + timeout))
+        .with_context(|| format!("`{}` did not finish within {:?}", command, timeout))
+}
+
+fn capture(session: &Session, command: &str, deadline: Option<Instant>) -> Result<ExecOutput> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let exit_status = exec_with(session, command, deadline, &mut stdout, &mut stderr)?;
+    Ok(ExecOutput {
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        exit_status,
+    })
+}
+
+/// Quotes `value` for safe interpolation into a POSIX shell command.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Returns what follows a leading `~`, `$HOME` or `${HOME}` in `path`, i.e.
+/// the part relative to the home directory, or `None` if `path` does not
+/// start in the home directory.
+pub fn home_relative(path: &str) -> Option<&str> {
+    ["~", "${HOME}", "$HOME"]
+        .iter()
+        .filter_map(|prefix| path.strip_prefix(prefix))
+        .find(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Replaces a leading `~`, `$HOME` or `${HOME}` in `path` with the home
+/// directory of the SSH user, which SFTP does not do on its own.
+pub fn expand_home(session: &Session, path: &str) -> Result<String> {
+    let Some(rest) = home_relative(path) else {
+        return Ok(path.to_string());
+    };
+    // SFTP sessions start in the home directory
+    let home = session
+        .sftp()?
+        .realpath(Path::new("."))
+        .context("Failed to resolve the remote home directory")?;
+    let expanded = format!("{}{}", home.to_string_lossy().trim_end_matches('/'), rest);
+    Ok(if expanded.is_empty() {
+        "/".to_string()
+    } else {
+        expanded
+    })
+}
+
+/// Returns whether `program` is available on the server's `PATH`.
+pub fn has_command(session: &Session, program: &str) -> Result<bool> {
+    let output = exec_capture(
+        session,
+        &format!("command -v {} >/dev/null 2>&1", shell_quote(program)),
+    )?;
+    Ok(output.success())
+}
+
+/// Extracts `remote_file` into `dest_dir` on the server using the tool that
+/// matches `format`, creating `dest_dir` if needed. A leading `~` in
+/// `dest_dir` is the SSH user's home directory, see [`expand_home`].
+pub fn extract_archive(
+    session: &Session,
+    remote_file: &str,
+    format: CompressionFormat,
+    dest_dir: &str,
+) -> Result<()> {
+    let dest_dir = &expand_home(session, dest_dir)?;
+    let (program, command) = match format {
+        CompressionFormat::Zip => (
+            "unzip",
+            format!(
+                "unzip -o -q {} -d {}",
+                shell_quote(remote_file),
+                shell_quote(dest_dir)
+            ),
+        ),
+        CompressionFormat::Tar => (
+            "tar",
+            format!(
+                "tar -xf {} -C {}",
+                shell_quote(remote_file),
+                shell_quote(dest_dir)
+            ),
+        ),
+        CompressionFormat::TarGz => (
+            "tar",
+            format!(
+                "tar -xzf {} -C {}",
+                shell_quote(remote_file),
+                shell_quote(dest_dir)
+            ),
+        ),
+    };
+
+    if !has_command(session, program)? {
+        return Err(anyhow::anyhow!(
+            "`{}` is not installed on the server, cannot extract {} archives",
+            program,
+            format.name()
+        ));
+    }
+
+    let output = exec_capture(
+        session,
+        &format!("mkdir -p {} && {}", shell_quote(dest_dir), command),
+    )?;
+    if !output.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to extract {} into {} (exit status {}): {}",
+            remote_file,
+            dest_dir,
+            output.exit_status,
+            output.stderr.trim()
+        ));
+    }
+    Ok(())
+}
+
+fn exec_with(
+    session: &Session,
+    command: &str,
+    deadline: Option<Instant>,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> Result<i32> {
+    let mut channel = session
+        .channel_session()
+        .context("Failed to open exec channel")?;
+    channel
+        .exec(command)
+        .with_context(|| format!("Failed to execute remote command: {}", command))?;
+    // Nothing is sent on stdin; without EOF a command reading it would hang
+    channel.send_eof()?;
+
+    // Both streams share one channel window, so they have to be drained
+    // alternately or a chatty stderr could stall stdout.
+    session.set_blocking(false);
+    let pumped = pump_streams(&mut channel, deadline, stdout, stderr);
+    session.set_blocking(true);
+    pumped.with_context(|| format!("Failed to read output of: {}", command))?;
+
+    channel.wait_close()?;
+    Ok(channel.exit_status()?)
+}
+
+fn pump_streams(
+    channel: &mut Channel,
+    deadline: Option<Instant>,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> Result<()> {
+    let mut buffer = [0u8; 8192];
+    loop {
+        let mut progressed = false;
+        if let Some(n) = read_nonblocking(channel, &mut buffer)? {
+            stdout.write_all(&buffer[..n])?;
+            progressed = true;
+        }
+        if let Some(n) = read_nonblocking(&mut channel.stderr(), &mut buffer)? {
+            stderr.write_all(&buffer[..n])?;
+            progressed = true;
+        }
+        if !progressed {
+            if channel.eof() {
+                break;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(anyhow::anyhow!("Timed out waiting for the command"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    stdout.flush()?;
+    stderr.flush()?;
+    Ok(())
+}
+
+fn read_nonblocking(stream: &mut impl Read, buffer: &mut [u8]) -> Result<Option<usize>> {
+    match stream.read(buffer) {
+        Ok(0) => Ok(None),
+        Ok(n) => Ok(Some(n)),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use ssh2::Sftp;
+
+use crate::compress::CompressionFormat;
+use crate::config::RetentionPolicy;
+use crate::template;
+use crate::units;
+
+/// An archive previously uploaded to a server's `remote_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteArchive {
+    pub name: String,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+}
+
+/// Whether `name` looks like an archive flash produced.
+///
+/// Partial uploads and `.prev` backups do not match, so they are never pruned.
+pub fn is_archive_name(name: &str) -> bool {
+    CompressionFormat::value_variants()
+        .iter()
+        .any(|format| name.ends_with(&format!(".{}", format.extension())))
+}
+
+/// Whether `name` is an archive flash could have uploaded with the
+/// `remote_name` template `template`: the literal parts of the template must
+/// match, each placeholder stands for any non-empty text. Without a template
+/// every archive name matches.
+pub fn matches_remote_name(name: &str, template: Option<&str>) -> bool {
+    if !is_archive_name(name) {
+        return false;
+    }
+    let Some(template) = template else {
+        return true;
+    };
+    // Placeholders are marked with NUL, which cannot occur in file names
+    let Ok(pattern) = template::render(template, |_, _| Ok("\0".to_string())) else {
+        return false;
+    };
+    let segments: Vec<&str> = pattern.split('\0').collect();
+    // The extension is appended unless the template produces it itself
+    matches_segments(&segments, name)
+        || CompressionFormat::value_variants().iter().any(|format| {
+            name.strip_suffix(&format!(".{}", format.extension()))
+                .is_some_and(|stem| matches_segments(&segments, stem))
+        })
+}
+
+/// Matches `name` against literal `segments` separated by wildcards of at
+/// least one character.
+fn matches_segments(segments: &[&str], name: &str) -> bool {
+    let (first, last) = match segments {
+        [only] => return name == *only,
+        [first, .., last] => (*first, *last),
+        [] => return false,
+    };
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    for segment in &segments[1..segments.len() - 1] {
+        let Some(skip) = rest.chars().next().map(char::len_utf8) else {
+            return false;
+        };
+        match rest[skip..].find(segment) {
+            Some(index) => rest = &rest[skip + index + segment.len()..],
+            None => return false,
+        }
+    }
+    rest.len() > last.len() && rest.ends_with(last)
+}
+
+/// Lists the archives directly inside `dir` on the server whose names match
+/// `template`, see [`matches_remote_name`].
+pub fn list_archives(sftp: &Sftp, dir: &str, template: Option<&str>) -> Result<Vec<RemoteArchive>> {
+    let entries = sftp
+        .readdir(Path::new(dir))
+        .with_context(|| format!("Failed to list remote directory: {}", dir))?;
+    Ok(entries
+        .into_iter()
+        .filter(|(_, stat)| stat.is_file())
+        .filter_map(|(path, stat)| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            matches_remote_name(&name, template).then(|| RemoteArchive {
+                name,
+                size: stat.size.unwrap_or(0),
+                mtime: stat.mtime.unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+/// Picks the archives `policy` wants removed.
+///
+/// Archives are ranked newest first; one is selected when it falls outside
+/// `keep_last`, is older than `max_age`, or pushes the running total of the
+/// newer archives past `max_total_size`. The names in `protect` (usually the
+/// archives that were just uploaded) are never selected.
+pub fn select_for_removal(
+    archives: &[RemoteArchive],
+    policy: &RetentionPolicy,
+    now: u64,
+    protect: &[&str],
+) -> Result<Vec<RemoteArchive>> {
+    let max_age = policy
+        .max_age
+        .as_deref()
+        .map(units::parse_duration)
+        .transpose()?;
+    let max_total_size = policy
+        .max_total_size
+        .as_deref()
+        .map(units::parse_size)
+        .transpose()?;
+
+    let mut ranked: Vec<&RemoteArchive> = archives.iter().collect();
+    ranked.sort_by(|a, b| b.mtime.cmp(&a.mtime).then_with(|| b.name.cmp(&a.name)));
+
+    let mut removed = Vec::new();
+    let mut total_size = 0u64;
+    let mut kept = 0usize;
+    for archive in ranked {
+        if protect.contains(&archive.name.as_str()) {
+            total_size += archive.size;
+            kept += 1;
+            continue;
+        }
+        let over_count = policy.keep_last.is_some_and(|keep| kept >= keep);
+        let too_old = max_age.is_some_and(|age| now.saturating_sub(archive.mtime) > age);
+        let over_size = max_total_size.is_some_and(|max| total_size + archive.size > max);
+        if over_count || too_old || over_size {
+            removed.push(archive.clone());
+        } else {
+            total_size += archive.size;
+            kept += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Applies `policy` to the archives in `dir` named after `template`, deleting
+/// the selected ones unless `dry_run` is set, and returns what was (or would
+/// be) removed.
+pub fn prune(
+    sftp: &Sftp,
+    dir: &str,
+    template: Option<&str>,
+    policy: &RetentionPolicy,
+    protect: &[&str],
+    dry_run: bool,
+) -> Result<Vec<RemoteArchive>> {
+    let archives = list_archives(sftp, dir, template)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let removed = select_for_removal(&archives, policy, now, protect)?;
+    if !dry_run {
+        for archive in &removed {
+            let path = format!("{}/{}", dir.trim_end_matches('/'), archive.name);
+            sftp.unlink(Path::new(&path))
+                .with_context(|| format!("Failed to remove remote archive: {}", path))?;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Whether `pattern` contains glob wildcards (`*`, `?` or `[...]`).
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Matches one path component against a glob `pattern`: `*` matches any run of
+/// characters, `?` one character, and `[abc]`, `[a-z]` or `[!abc]` one
+/// character of (or not of) a set.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, end)) = match_class(&pattern[p..], name[n]) {
+                    if matched {
+                        p += end;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    // An unclosed `[` is a literal
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star, tried)) => {
+                p = star;
+                n = tried + 1;
+                backtrack = Some((star, tried + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the class at the start of `pattern`, returning whether
+/// it matched and the length of the class, or `None` if it is not closed.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut index = 1;
+    let negated = matches!(pattern.get(index), Some('!' | '^'));
+    if negated {
+        index += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(index)?;
+        if start == ']' && !first {
+            return Some((matched != negated, index + 1));
+        }
+        first = false;
+        if pattern.get(index + 1) == Some(&'-') && pattern.get(index + 2).is_some_and(|&e| e != ']')
+        {
+            let end = pattern[index + 2];
+            matched |= start <= c && c <= end;
+            index += 3;
+        } else {
+            matched |= start == c;
+            index += 1;
+        }
+    }
+}
+
+/// Expands the paths and glob patterns given on the command line into the
+/// existing files and directories they name, in the given order and without
+/// duplicates. A pattern matching nothing is an error.
+pub fn expand(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    for pattern in patterns {
+        let matches = if is_glob(pattern) {
+            expand_glob(pattern)?
+        } else if Path::new(pattern).exists() {
+            vec![PathBuf::from(pattern)]
+        } else {
+            return Err(anyhow::anyhow!("Input path does not exist: {}", pattern));
+        };
+        if matches.is_empty() {
+            return Err(anyhow::anyhow!("No files match {}", pattern));
+        }
+        for path in matches {
+            if !inputs.contains(&path) {
+                inputs.push(path);
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+/// Paths matching `pattern`, sorted. `**` matches any number of directories;
+/// hidden entries only match components that start with a dot.
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = match component {
+            Component::Normal(part) => part.to_string_lossy().into_owned(),
+            other => {
+                let other = other.as_os_str();
+                candidates = candidates.iter().map(|path| path.join(other)).collect();
+                continue;
+            }
+        };
+        let mut next = Vec::new();
+        for base in &candidates {
+            if part == "**" {
+                next.push(base.clone());
+                next.extend(subdirectories(base)?);
+            } else if is_glob(&part) {
+                for name in list_dir(base)? {
+                    if (part.starts_with('.') || !name.starts_with('.')) && glob_match(&part, &name)
+                    {
+                        next.push(base.join(name));
+                    }
+                }
+            } else {
+                let path = base.join(&part);
+                if fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+            }
+        }
+        candidates = next;
+    }
+    candidates.retain(|path| !path.as_os_str().is_empty());
+    candidates.sort();
+    candidates.dedup();
+    Ok(candidates)
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<String>> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+/// All directories below `dir`, skipping hidden ones.
+fn subdirectories(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for name in list_dir(dir)? {
+        let path = dir.join(&name);
+        if !name.starts_with('.') && path.is_dir() {
+            found.extend(subdirectories(&path)?);
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Names the inputs of a bundle get at the top level of the archive: their
+/// file names. Two inputs with the same name are an error.
+pub fn entry_names(inputs: &[PathBuf]) -> Result<Vec<String>> {
+    let mut seen: HashMap<String, &Path> = HashMap::new();
+    let mut names = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let name = file_name(input)?;
+        if let Some(other) = seen.insert(name.clone(), input) {
+            return Err(anyhow::anyhow!(
+                "{} and {} would both be stored as '{}', pass them separately",
+                other.display(),
+                input.display(),
+                name
+            ));
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Directory containing all `inputs`, which names a bundle of them.
+pub fn common_dir(inputs: &[PathBuf]) -> Result<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for input in inputs {
+        let path = fs::canonicalize(input)
+            .with_context(|| format!("Failed to resolve {}", input.display()))?;
+        let parent = path.parent().unwrap_or(&path).to_path_buf();
+        common = Some(match common {
+            None => parent,
+            Some(common) => common
+                .ancestors()
+                .find(|ancestor| parent.starts_with(ancestor))
+                .unwrap_or(Path::new("/"))
+                .to_path_buf(),
+        });
+    }
+    common.ok_or_else(|| anyhow::anyhow!("No input paths given"))
+}
+
+/// Name of the file or directory `path` refers to, resolving `.` and `..`.
+pub fn file_name(path: &Path) -> Result<String> {
+    match path.file_name() {
+        Some(name) if !path.ends_with("..") => Ok(name.to_string_lossy().into_owned()),
+        _ => fs::canonicalize(path)
+            .ok()
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .ok_or_else(|| anyhow::anyhow!("Invalid input path: {}", path.display())),
+    }
+}
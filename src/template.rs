@@ -0,0 +1,232 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+use crate::compress::CompressionFormat;
+use crate::sources;
+
+/// Placeholders understood by [`RemoteNameContext`].
+pub const PLACEHOLDERS: &[&str] = &[
+    "name",
+    "ext",
+    "date",
+    "time",
+    "git_sha",
+    "git_branch",
+    "hostname",
+    "user",
+    "version",
+];
+
+/// Replaces every `{key}` or `{key:arg}` in `template` with what `lookup`
+/// returns for it. `{{` and `}}` stand for literal braces.
+pub fn render(
+    template: &str,
+    mut lookup: impl FnMut(&str, Option<&str>) -> Result<String>,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        output.push_str(&rest[..index]);
+        let tail = &rest[index..];
+        if let Some(after) = tail.strip_prefix("{{") {
+            output.push('{');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("}}") {
+            output.push('}');
+            rest = after;
+        } else if tail.starts_with('}') {
+            return Err(anyhow::anyhow!("Unmatched '}}' in template: {}", template));
+        } else {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unclosed '{{' in template: {}", template))?;
+            let placeholder = &tail[1..end];
+            let (key, arg) = match placeholder.split_once(':') {
+                Some((key, arg)) => (key, Some(arg)),
+                None => (placeholder, None),
+            };
+            output.push_str(&lookup(key.trim(), arg)?);
+            rest = &tail[end + 1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Splits a file name into its stem and extension, treating `.tar.gz` as a
+/// single extension.
+pub fn split_extension(file_name: &str) -> (&str, &str) {
+    let lower = file_name.to_lowercase();
+    if lower.ends_with(".tar.gz") && file_name.len() > ".tar.gz".len() {
+        let split = file_name.len() - ".tar.gz".len();
+        return (&file_name[..split], &file_name[split + 1..]);
+    }
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (file_name, ""),
+    }
+}
+
+/// Values for the placeholders of a `remote_name` template.
+///
+/// Git and version lookups run only for templates that use them.
+#[derive(Debug, Clone)]
+pub struct RemoteNameContext {
+    /// Name of the uploaded file or directory, without archive extension.
+    pub name: String,
+    /// Extension the remote file must end with, e.g. `tar.gz`.
+    pub ext: String,
+    /// Directory git and version information is read from.
+    pub source_dir: PathBuf,
+    pub now: DateTime<Local>,
+}
+
+impl RemoteNameContext {
+    /// Context for uploading `source` compressed with `format`. `.` and `..`
+    /// are named after the directory they resolve to.
+    pub fn for_archive(source: &Path, format: CompressionFormat) -> Self {
+        let name = sources::file_name(source).unwrap_or_default();
+        Self::new(source, name, format.extension().to_string())
+    }
+
+    /// Context for uploading the existing file `file` as it is.
+    pub fn for_file(file: &Path) -> Self {
+        let file_name = file_name(file);
+        let (stem, ext) = split_extension(&file_name);
+        Self::new(file, stem.to_string(), ext.to_string())
+    }
+
+    fn new(source: &Path, name: String, ext: String) -> Self {
+        let source_dir = if source.is_dir() {
+            source.to_path_buf()
+        } else {
+            source
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map_or_else(|| PathBuf::from("."), Path::to_path_buf)
+        };
+        Self {
+            name,
+            ext,
+            source_dir,
+            now: Local::now(),
+        }
+    }
+
+    /// Renders `template` into a remote file name. The extension is appended
+    /// unless the template already produces it.
+    pub fn remote_name(&self, template: &str) -> Result<String> {
+        let rendered = render(template, |key, arg| self.value(key, arg))
+            .with_context(|| format!("Invalid remote_name template '{}'", template))?;
+        if rendered.is_empty() || rendered.contains('/') || rendered == "." || rendered == ".." {
+            return Err(anyhow::anyhow!(
+                "remote_name template '{}' must produce a file name, got '{}'",
+                template,
+                rendered
+            ));
+        }
+        let suffix = format!(".{}", self.ext);
+        if self.ext.is_empty() || rendered.to_lowercase().ends_with(&suffix.to_lowercase()) {
+            Ok(rendered)
+        } else {
+            Ok(rendered + &suffix)
+        }
+    }
+
+    fn value(&self, key: &str, arg: Option<&str>) -> Result<String> {
+        let value = match key {
+            "name" => self.name.clone(),
+            "ext" => self.ext.clone(),
+            "date" => format_time(&self.now, arg.unwrap_or("%Y-%m-%d"))?,
+            "time" => format_time(&self.now, arg.unwrap_or("%H%M%S"))?,
+            "git_sha" => self.git(&["rev-parse", "--short", "HEAD"])?,
+            "git_branch" => self.git(&["rev-parse", "--abbrev-ref", "HEAD"])?,
+            "hostname" => hostname()?,
+            "user" => env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .context("Cannot tell the user name, USER is not set")?,
+            "version" => self.version()?,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown placeholder {{{}}}, expected one of: {}",
+                    key,
+                    PLACEHOLDERS.join(", ")
+                ))
+            }
+        };
+        // Branch names such as feature/login must not create directories
+        Ok(value.replace(['/', '\\'], "-"))
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.source_dir)
+            .args(args)
+            .output()
+            .context("Failed to run git")?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{} is not inside a git repository",
+                self.source_dir.display()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Version of the project being uploaded: the package version in its
+    /// `Cargo.toml`, or else `git describe --tags`.
+    fn version(&self) -> Result<String> {
+        if let Some(version) = cargo_version(&self.source_dir.join("Cargo.toml")) {
+            return Ok(version);
+        }
+        self.git(&["describe", "--tags", "--always"])
+            .context("No Cargo.toml version or git tag to take {version} from")
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn format_time(now: &DateTime<Local>, format: &str) -> Result<String> {
+    use std::fmt::Write;
+    let mut formatted = String::new();
+    write!(formatted, "{}", now.format(format))
+        .map_err(|_| anyhow::anyhow!("Invalid date format '{}'", format))?;
+    Ok(formatted)
+}
+
+fn cargo_version(manifest: &Path) -> Option<String> {
+    let content = fs::read_to_string(manifest).ok()?;
+    let manifest: toml::Value = toml::from_str(&content).ok()?;
+    manifest
+        .get("package")?
+        .get("version")?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(unix)]
+fn hostname() -> Result<String> {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to read the hostname");
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Ok(String::from_utf8_lossy(&buffer[..end]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Result<String> {
+    env::var("COMPUTERNAME").context("Cannot tell the hostname, COMPUTERNAME is not set")
+}
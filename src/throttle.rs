@@ -0,0 +1,222 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::units;
+
+/// How often the control file of a [`Throttle`] is checked for changes.
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token bucket limiting a transfer to `rate` bytes per second, with bursts
+/// of up to one second's worth of data. A rate of 0 means unlimited.
+///
+/// Bytes taken beyond the available tokens are borrowed and paid back by
+/// waiting, so a single large write is allowed but delays the next one.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Changes the rate, keeping at most one second's worth of saved tokens.
+    ///
+    /// Borrowed tokens are forgiven: the writer that borrowed them is already
+    /// waiting them off at the old rate, and repaying them at a new, lower
+    /// rate would stall the transfer far longer than either rate implies.
+    pub fn set_rate(&mut self, rate: u64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.clamp(0.0, rate as f64);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes `bytes` tokens and returns how long to wait before sending them.
+    pub fn reserve(&mut self, bytes: u64, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+
+    /// Gives back `bytes` reserved tokens that were not sent after all.
+    pub fn refund(&mut self, bytes: u64) {
+        self.tokens = (self.tokens + bytes as f64).min(self.rate as f64);
+    }
+}
+
+/// Bandwidth limit shared by all connections of one upload.
+///
+/// With a control file, the limit can be changed while the upload runs by
+/// writing a new rate such as `2M` (or `0` for unlimited) into the file.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    bucket: Arc<Mutex<TokenBucket>>,
+    control: Option<Arc<ControlFile>>,
+}
+
+#[derive(Debug)]
+struct ControlFile {
+    path: PathBuf,
+    /// Time of the last check and the modification time seen then.
+    state: Mutex<(Option<Instant>, Option<SystemTime>)>,
+}
+
+impl Throttle {
+    /// Limits to `rate` bytes per second, 0 meaning unlimited.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(rate, Instant::now()))),
+            control: None,
+        }
+    }
+
+    /// Like [`Throttle::new`], taking the rate from `path` whenever the file
+    /// changes. An existing file overrides `rate` right away.
+    pub fn with_control_file(rate: u64, path: &Path) -> Self {
+        let throttle = Self {
+            control: Some(Arc::new(ControlFile {
+                path: path.to_path_buf(),
+                state: Mutex::new((None, None)),
+            })),
+            ..Self::new(rate)
+        };
+        throttle.poll_control_file();
+        throttle
+    }
+
+    /// Current limit in bytes per second, 0 meaning unlimited.
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate()
+    }
+
+    pub fn set_rate(&self, rate: u64) {
+        self.bucket.lock().unwrap().set_rate(rate, Instant::now());
+    }
+
+    /// Largest write that keeps the transfer smooth at the current rate.
+    pub fn max_write(&self) -> usize {
+        match self.rate() {
+            0 => usize::MAX,
+            rate => usize::try_from(rate / 4).unwrap_or(usize::MAX).max(4096),
+        }
+    }
+
+    /// Blocks until `bytes` may be sent.
+    pub fn acquire(&self, bytes: u64) {
+        self.poll_control_file();
+        let wait = self.bucket.lock().unwrap().reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Returns `bytes` acquired but not sent, e.g. after a short write.
+    pub fn release(&self, bytes: u64) {
+        self.bucket.lock().unwrap().refund(bytes);
+    }
+
+    fn poll_control_file(&self) {
+        let Some(control) = &self.control else {
+            return;
+        };
+        let mut state = control.state.lock().unwrap();
+        let (last_check, last_modified) = &mut *state;
+        if last_check.is_some_and(|checked| checked.elapsed() < CONTROL_POLL_INTERVAL) {
+            return;
+        }
+        *last_check = Some(Instant::now());
+
+        let Ok(modified) = fs::metadata(&control.path).and_then(|meta| meta.modified()) else {
+            return;
+        };
+        if *last_modified == Some(modified) {
+            return;
+        }
+        *last_modified = Some(modified);
+
+        match read_rate(&control.path) {
+            Ok(rate) => {
+                info!("Bandwidth limit set to {} B/s", rate);
+                self.set_rate(rate);
+            }
+            Err(e) => warn!("Ignoring {}: {}", control.path.display(), e),
+        }
+    }
+}
+
+/// Parses a rate such as `5M`, `500K` or `0`/`off` for unlimited.
+pub fn parse_rate(value: &str) -> Result<u64> {
+    match value.trim() {
+        "off" | "unlimited" => Ok(0),
+        rate => units::parse_size(rate),
+    }
+}
+
+fn read_rate(path: &Path) -> Result<u64> {
+    parse_rate(&fs::read_to_string(path)?)
+}
+
+/// Writer that waits for its [`Throttle`] before passing data on.
+pub struct ThrottledWriter<'a, W> {
+    inner: W,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, W: Write> ThrottledWriter<'a, W> {
+    /// Wraps `inner`; without a throttle, writes pass straight through.
+    pub fn new(inner: W, throttle: Option<&'a Throttle>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(throttle) = self.throttle else {
+            return self.inner.write(buf);
+        };
+        let len = buf.len().min(throttle.max_write());
+        throttle.acquire(len as u64);
+        let result = self.inner.write(&buf[..len]);
+        let written = *result.as_ref().unwrap_or(&0);
+        if written < len {
+            throttle.release((len - written) as u64);
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
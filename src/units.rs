@@ -0,0 +1,51 @@
+use anyhow::Result;
+
+/// Parses a byte size such as `512`, `64K`, `1.5M` or `50G`.
+///
+/// Suffixes are binary (`K` = 1024) and case-insensitive; a trailing `B` or
+/// `iB` is accepted, so `50GB` and `50GiB` mean the same as `50G`.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let trimmed = value.trim();
+    let upper = trimmed.to_ascii_uppercase();
+    let unit_trimmed = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (number, multiplier) = match unit_trimmed.chars().last() {
+        Some('K') => (&unit_trimmed[..unit_trimmed.len() - 1], 1u64 << 10),
+        Some('M') => (&unit_trimmed[..unit_trimmed.len() - 1], 1u64 << 20),
+        Some('G') => (&unit_trimmed[..unit_trimmed.len() - 1], 1u64 << 30),
+        Some('T') => (&unit_trimmed[..unit_trimmed.len() - 1], 1u64 << 40),
+        _ => (unit_trimmed, 1),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {}", value))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(anyhow::anyhow!("Invalid size: {}", value));
+    }
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parses a duration such as `90s`, `15m`, `12h`, `30d` or `2w` into seconds.
+pub fn parse_duration(value: &str) -> Result<u64> {
+    let trimmed = value.trim();
+    let invalid = || anyhow::anyhow!("Invalid duration: {}", value);
+    let unit = trimmed.chars().last().ok_or_else(invalid)?;
+    let seconds_per_unit = match unit.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = trimmed[..trimmed.len() - 1]
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+    number
+        .checked_mul(seconds_per_unit)
+        .ok_or_else(|| anyhow::anyhow!("Duration is too long: {}", value))
+}
@@ -1,43 +1,222 @@
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::net::TcpStream;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
-use sha2::{Digest, Sha256};
-use ssh2::{OpenFlags, Session};
-
-use crate::loading::LoadingSpinner;
-
-fn calc_file_sha256(path: &str) -> anyhow::Result<String> {
-    let mut file = File::open(path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
-    loop {
-        let n = file.read(&mut buffer)?;
-        if n == 0 {
-            break;
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use log::{debug, warn};
+use ssh2::{FileStat, OpenFlags, RenameFlags, Session, Sftp};
+
+use crate::checksum::{self, ChecksumAlgorithm, HashingWriter};
+use crate::compress::CompressionFormat;
+use crate::loading::TransferProgress;
+use crate::remote;
+use crate::throttle::{Throttle, ThrottledWriter};
+
+/// Digest of a remote file with the upload's checksum algorithm.
+fn remote_checksum(
+    session: &Session,
+    sftp: &Sftp,
+    remote_path: &str,
+    options: &UploadOptions,
+) -> Result<String> {
+    checksum::remote_digest(
+        session,
+        sftp,
+        options.checksum,
+        remote_path,
+        options.buffer_size,
+    )
+}
+
+/// Copies `reader` into `writer` in pieces of up to `buffer_size` bytes and
+/// returns the number of bytes copied.
+///
+/// The reader runs on its own thread and may be up to [`PIPELINE_DEPTH`]
+/// buffers ahead, so local reads overlap with writes to the network.
+/// `on_write` is called with the size of each piece once it was written.
+pub fn pipelined_copy<R: Read + Send, W: Write>(
+    mut reader: R,
+    writer: &mut W,
+    buffer_size: usize,
+    mut on_write: impl FnMut(u64),
+) -> Result<u64> {
+    let buffer_size = buffer_size.max(1);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel::<io::Result<Vec<u8>>>(PIPELINE_DEPTH);
+        scope.spawn(move || loop {
+            let mut buffer = vec![0u8; buffer_size];
+            let piece = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buffer.truncate(n);
+                    Ok(buffer)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = piece.is_err();
+            // The writer hung up after an error of its own
+            if sender.send(piece).is_err() || failed {
+                break;
+            }
+        });
+
+        let mut copied = 0;
+        for piece in receiver {
+            let piece = piece.context("Failed to read local file")?;
+            writer.write_all(&piece)?;
+            copied += piece.len() as u64;
+            on_write(piece.len() as u64);
+        }
+        Ok(copied)
+    })
+}
+
+/// Suffix of the temporary file an upload is written to before being renamed
+/// into place. An existing partial file is also where interrupted uploads resume.
+pub const PARTIAL_SUFFIX: &str = ".flash-partial";
+
+/// Suffix the replaced remote file is kept under when `keep_previous` is set.
+pub const PREVIOUS_SUFFIX: &str = ".prev";
+
+/// Size of the buffers files are read, written and hashed with, unless
+/// configured otherwise. libssh2 splits a large write into several SFTP
+/// packets that are in flight at once, so larger buffers mean fewer round
+/// trips per byte.
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Number of filled buffers the local reader may be ahead of the upload.
+pub const PIPELINE_DEPTH: usize = 4;
+
+/// Smallest range a parallel upload hands to one connection. Smaller files
+/// are not worth the extra SSH handshakes.
+pub const MIN_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Address and credentials of the SSH server to upload to.
+#[derive(Debug, Clone)]
+pub struct SshTarget {
+    pub ip: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Keep the file being replaced as `<name>.prev` instead of discarding it.
+    pub keep_previous: bool,
+    /// Permissions of remote directories created for the upload.
+    pub dir_mode: i32,
+    /// Permissions of the uploaded file.
+    pub file_mode: i32,
+    /// Numeric user id to chown the uploaded file to.
+    pub uid: Option<u32>,
+    /// Numeric group id to chgrp the uploaded file to.
+    pub gid: Option<u32>,
+    /// Number of SSH connections a large file is uploaded over in parallel.
+    pub connections: usize,
+    /// Size of the read and write buffers, see [`DEFAULT_BUFFER_SIZE`].
+    pub buffer_size: usize,
+    /// Bandwidth limit, shared by all connections of a parallel upload.
+    pub throttle: Option<Throttle>,
+    /// Hash the upload is verified with.
+    pub checksum: ChecksumAlgorithm,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            keep_previous: false,
+            dir_mode: 0o755,
+            file_mode: 0o644,
+            uid: None,
+            gid: None,
+            connections: 1,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            throttle: None,
+            checksum: ChecksumAlgorithm::default(),
+        }
+    }
+}
+
+/// Splits a file of `size` bytes into at most `connections` contiguous ranges
+/// of at least `min_chunk` bytes each.
+pub fn chunk_ranges(size: u64, connections: usize, min_chunk: u64) -> Vec<Range<u64>> {
+    let count = (connections as u64).min(size / min_chunk.max(1)).max(1);
+    let chunk = size.div_ceil(count);
+    (0..count)
+        .map(|index| index * chunk..((index + 1) * chunk).min(size))
+        .filter(|range| !range.is_empty() || size == 0)
+        .collect()
+}
+
+/// Creates `dir` on the server along with any missing parents, like `mkdir -p`.
+pub fn create_remote_dir_all(sftp: &Sftp, dir: &Path, mode: i32) -> Result<()> {
+    let mut current = PathBuf::new();
+    for component in dir.components() {
+        current.push(component);
+        if !matches!(component, Component::Normal(_)) || sftp.stat(&current).is_ok() {
+            continue;
+        }
+        if let Err(e) = sftp.mkdir(&current, mode) {
+            // Another uploader may have created it in the meantime
+            if sftp.stat(&current).is_err() {
+                return Err(e).with_context(|| {
+                    format!("Failed to create remote directory: {}", current.display())
+                });
+            }
         }
-        hasher.update(&buffer[..n]);
     }
-    Ok(format!("{:x}", hasher.finalize()))
+    Ok(())
+}
+
+fn open_flags_truncate() -> OpenFlags {
+    OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE
 }
 
-fn calc_remote_sha256(sftp: &ssh2::Sftp, remote_path: &str) -> anyhow::Result<String> {
-    let mut remote_file = sftp.open(remote_path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
-    loop {
-        let n = remote_file.read(&mut buffer)?;
-        if n == 0 {
-            break;
+/// Renames `from` to `to`, replacing `to` if it already exists.
+///
+/// SFTPv3 servers such as OpenSSH ignore the overwrite flag and refuse to
+/// rename onto an existing file. `mv -f` is then run on the server instead,
+/// which replaces the file atomically with rename(2). Only if that is not
+/// possible either is the target removed before renaming, which briefly
+/// leaves no file at `to`.
+fn rename_overwrite(session: &Session, sftp: &Sftp, from: &Path, to: &Path) -> Result<()> {
+    let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+    if sftp.rename(from, to, Some(flags)).is_ok() {
+        return Ok(());
+    }
+    match sftp.lstat(to) {
+        // `mv` would move a file into a directory rather than replace it
+        Ok(stat) if !stat.is_dir() => {
+            let command = format!(
+                "mv -f {} {}",
+                remote::shell_quote(&from.to_string_lossy()),
+                remote::shell_quote(&to.to_string_lossy())
+            );
+            match remote::exec_capture(session, &command) {
+                Ok(output) if output.success() => return Ok(()),
+                Ok(output) => debug!("`{}` failed: {}", command, output.stderr.trim()),
+                Err(e) => debug!("`{}` failed: {:#}", command, e),
+            }
+            warn!(
+                "Cannot replace {} atomically on the server, removing it before the rename",
+                to.display()
+            );
+            sftp.unlink(to)
+                .with_context(|| format!("Failed to replace remote file: {}", to.display()))?;
         }
-        hasher.update(&buffer[..n]);
+        _ => {}
     }
-    Ok(format!("{:x}", hasher.finalize()))
+    sftp.rename(from, to, Some(flags))
+        .with_context(|| format!("Failed to rename {} to {}", from.display(), to.display()))?;
+    Ok(())
 }
 
 pub fn upload_via_sftp(
@@ -48,127 +227,624 @@ pub fn upload_via_sftp(
     local_zip: &str,
     remote_path: &str,
 ) -> Result<()> {
-    let loading = LoadingSpinner::new("Connecting to SFTP server...");
+    let target = SshTarget {
+        ip: ip.to_string(),
+        port,
+        username: username.to_string(),
+        password: password.to_string(),
+    };
+    upload_via_sftp_with_options(&target, local_zip, remote_path, &UploadOptions::default())
+        .map(|_| ())
+}
+
+/// Result of a successful [`upload_file`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadOutcome {
+    Uploaded,
+    /// The remote file already matched the local one.
+    Skipped,
+}
+
+/// Opens an authenticated SSH session to `target`.
+pub fn connect(target: &SshTarget) -> Result<Session> {
+    let progress = TransferProgress::new();
+    let session = open_session(target, &progress);
+    match &session {
+        Ok(_) => progress.finish_and_clear(),
+        Err(_) => progress.finish_with_error("Connection failed"),
+    }
+    session
+}
+
+/// Opens an authenticated SSH session to `target`, reporting on `progress`.
+pub fn open_session(target: &SshTarget, progress: &TransferProgress) -> Result<Session> {
+    open_session_with(target, |message| progress.set_message(message))
+}
+
+/// Opens an authenticated SSH session to `target`, passing each step to
+/// `report`.
+fn open_session_with(target: &SshTarget, report: impl Fn(&str)) -> Result<Session> {
+    report("Connecting to SFTP server...");
 
-    let tcp = TcpStream::connect(format!("{}:{}", ip, port))?;
+    let tcp = TcpStream::connect(format!("{}:{}", target.ip, target.port))?;
     let mut session = Session::new()?;
     session.set_tcp_stream(tcp);
     session.handshake()?;
 
-    loading.update_message("Authenticating...");
-    session.userauth_password(username, password)?;
+    report("Authenticating...");
+    session.userauth_password(&target.username, &target.password)?;
     if !session.authenticated() {
-        loading.finish_with_error("Authentication failed");
         return Err(anyhow::anyhow!("Authentication failed"));
     }
 
-    loading.update_message("Checking remote directory...");
+    Ok(session)
+}
+
+/// Connects to `target` and uploads `local_zip` to `remote_path`, returning the
+/// session so follow-up work can reuse it.
+pub fn upload_via_sftp_with_options(
+    target: &SshTarget,
+    local_zip: &str,
+    remote_path: &str,
+    options: &UploadOptions,
+) -> Result<Session> {
+    let progress = TransferProgress::new();
+    let result = upload_attempt(target, &[(local_zip, remote_path)], options, &progress);
+    finish_upload(
+        &progress,
+        result.as_ref().ok().map(|(_, outcomes)| outcomes[0]),
+        target,
+        remote_path,
+    );
+    result.map(|(session, _)| session)
+}
+
+/// Reports the final outcome of an upload on `progress`, `None` meaning it
+/// failed.
+pub fn finish_upload(
+    progress: &TransferProgress,
+    outcome: Option<UploadOutcome>,
+    target: &SshTarget,
+    remote_path: &str,
+) {
+    match outcome {
+        Some(UploadOutcome::Uploaded) => progress.finish_with_success(&format!(
+            "File uploaded successfully to ({}) {}",
+            target.ip, remote_path
+        )),
+        Some(UploadOutcome::Skipped) => {
+            progress.finish_with_success("File already exists and is identical. Skipped upload.")
+        }
+        None => progress.finish_with_error("Upload failed"),
+    }
+}
+
+/// Uploads each `(local, remote)` pair in turn over one new session.
+fn upload_attempt(
+    target: &SshTarget,
+    files: &[(&str, &str)],
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<(Session, Vec<UploadOutcome>)> {
+    let session = open_session(target, progress)?;
+    let mut outcomes = Vec::with_capacity(files.len());
+    for (local_zip, remote_path) in files {
+        let remote_path = remote::expand_home(&session, remote_path)?;
+        outcomes.push(upload_file_parallel(
+            &session,
+            target,
+            local_zip,
+            &remote_path,
+            options,
+            progress,
+        )?);
+    }
+    Ok((session, outcomes))
+}
+
+/// Uploads `local_zip` to `remote_path` over an established session without
+/// ever exposing a half-written file at the final location.
+///
+/// Data is written to `<remote_path>.flash-partial`, verified against the local
+/// checksum (computed on the server when it has a suitable tool, otherwise
+/// read back over SFTP) and only then renamed over `remote_path`. `progress` is left
+/// running so the caller can report the final outcome on it.
+pub fn upload_file(
+    session: &Session,
+    local_zip: &str,
+    remote_path: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<UploadOutcome> {
+    upload_file_with(session, None, local_zip, remote_path, options, progress)
+}
+
+/// Like [`upload_file`], but a fresh upload of a large file is split into
+/// `options.connections` ranges, each written over its own session to
+/// `target` with positioned writes. The assembled file is verified against
+/// the full local checksum before it is moved into place.
+pub fn upload_file_parallel(
+    session: &Session,
+    target: &SshTarget,
+    local_zip: &str,
+    remote_path: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<UploadOutcome> {
+    upload_file_with(
+        session,
+        Some(target),
+        local_zip,
+        remote_path,
+        options,
+        progress,
+    )
+}
+
+fn upload_file_with(
+    session: &Session,
+    target: Option<&SshTarget>,
+    local_zip: &str,
+    remote_path: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<UploadOutcome> {
+    progress.spin("Checking remote directory...");
     let sftp = session.sftp()?;
     let remote_file_path = Path::new(remote_path);
+    let partial_path_string = format!("{}{}", remote_path, PARTIAL_SUFFIX);
+    let partial_path = Path::new(&partial_path_string);
 
     // Ensure remote directory exists
     if let Some(parent_dir) = remote_file_path.parent() {
-        if let Err(_) = sftp.stat(parent_dir) {
-            loading.update_message("Creating remote directory...");
-            sftp.mkdir(parent_dir, 0o755)?;
+        if sftp.stat(parent_dir).is_err() {
+            progress.set_message("Creating remote directory...");
+            create_remote_dir_all(&sftp, parent_dir, options.dir_mode)?;
         }
     }
 
-    loading.update_message("Analyzing files...");
-    let remote_size = match sftp.stat(remote_file_path) {
+    progress.set_message("Analyzing files...");
+    let mut file = File::open(local_zip)?;
+    let file_size = file.metadata()?.len();
+    let local_hash = checksum::hash_file(options.checksum, local_zip, options.buffer_size)?;
+
+    let existing_size = match sftp.stat(remote_file_path) {
         Ok(stat) => stat.size.unwrap_or(0),
-        Err(_) => 0, // If the file doesn't exist, start from 0
+        Err(_) => 0,
     };
+    if existing_size == file_size && file_size > 0 {
+        if let Ok(remote_hash) = remote_checksum(session, &sftp, remote_path, options) {
+            if remote_hash == local_hash {
+                return Ok(UploadOutcome::Skipped);
+            }
+        }
+    }
 
-    // Finish the loading spinner before starting the upload progress bar
-    loading.finish_with_success("Ready to upload");
-
-    let mut file = File::open(local_zip)?;
-    let file_size = file.metadata()?.len();
+    let partial_size = match sftp.stat(partial_path) {
+        Ok(stat) => stat.size.unwrap_or(0),
+        Err(_) => 0, // If the file doesn't exist, start from 0
+    };
 
-    let (start_pos, open_flags) = if remote_size == file_size && file_size > 0 {
-        match calc_remote_sha256(&sftp, remote_path) {
-            Ok(remote_hash) => {
-                let local_hash = calc_file_sha256(local_zip)?;
-                if local_hash == remote_hash {
-                    println!("File already exists and is identical. Skipping upload.");
-                    return Ok(());
-                } else {
-                    println!("File exists but differs. Overwriting...");
-                    (
-                        0,
-                        OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
-                    )
-                }
+    let (start_pos, open_flags) = if partial_size == file_size && file_size > 0 {
+        match remote_checksum(session, &sftp, &partial_path_string, options) {
+            Ok(remote_hash) if remote_hash == local_hash => {
+                progress.println("Partial upload is already complete.");
+                (file_size, OpenFlags::WRITE | OpenFlags::APPEND)
+            }
+            Ok(_) => {
+                progress.println("Partial upload differs. Overwriting...");
+                (0, open_flags_truncate())
             }
             Err(_) => {
-                println!("Failed to calculate remote file hash, overwriting...");
-                (
-                    0,
-                    OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
-                )
+                progress.println("Failed to calculate remote file hash, overwriting...");
+                (0, open_flags_truncate())
             }
         }
-    } else if remote_size > 0 && remote_size < file_size {
-        println!("Resuming upload from position: {}", remote_size);
-        (remote_size, OpenFlags::WRITE | OpenFlags::APPEND)
+    } else if partial_size > 0 && partial_size < file_size {
+        progress.println(&format!("Resuming upload from position: {}", partial_size));
+        (partial_size, OpenFlags::WRITE | OpenFlags::APPEND)
     } else {
-        println!("Starting fresh upload");
-        (
-            0,
-            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
-        )
+        progress.println("Starting fresh upload");
+        (0, open_flags_truncate())
     };
 
-    file.seek(SeekFrom::Start(start_pos))?;
+    let chunks = match target {
+        // A resumed upload continues sequentially from the end of the partial file
+        Some(target) if start_pos == 0 => Some((
+            target,
+            chunk_ranges(file_size, options.connections, MIN_CHUNK_SIZE),
+        )),
+        _ => None,
+    };
+
+    match chunks {
+        Some((target, chunks)) if chunks.len() > 1 => {
+            progress.println(&format!("Uploading in {} parallel chunks", chunks.len()));
+            progress.start_transfer(file_size, 0);
+            if let Err(e) = upload_chunks(
+                session,
+                target,
+                local_zip,
+                partial_path,
+                &chunks,
+                options,
+                progress,
+            ) {
+                // Finished chunks leave holes before unfinished ones, so the
+                // partial file cannot be resumed from
+                let _ = sftp.unlink(partial_path);
+                return Err(e);
+            }
+        }
+        _ => {
+            file.seek(SeekFrom::Start(start_pos))?;
+            progress.start_transfer(file_size, start_pos);
+
+            let mut remote_file = sftp.open_mode(
+                partial_path,
+                open_flags,
+                options.file_mode,
+                ssh2::OpenType::File,
+            )?;
+            let mut total_written = start_pos;
+            let mut writer = ThrottledWriter::new(&mut remote_file, options.throttle.as_ref());
+            pipelined_copy(file, &mut writer, options.buffer_size, |n| {
+                total_written += n;
+                progress.set_position(total_written);
+            })?;
+            remote_file.close()?;
+        }
+    }
+
+    move_into_place(session, &sftp, remote_path, &local_hash, options, progress)?;
+    Ok(UploadOutcome::Uploaded)
+}
 
-    // progress bar
-    let pb = ProgressBar::new(file_size);
-    pb.set_position(start_pos);
-    pb.set_style(ProgressStyle::default_bar().template(
-        "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})"
-    ).unwrap().progress_chars("#>-"));
+/// Attributes the uploaded file is given: the configured mode and, when set,
+/// owner.
+///
+/// SFTP sets user and group together, so when only one of them is configured
+/// the other is taken from `current`, the file's present attributes. Without
+/// those the ownership is left alone rather than handing the file to root.
+pub fn attribute_stat(options: &UploadOptions, current: Option<&FileStat>) -> FileStat {
+    let (uid, gid) = match (options.uid, options.gid) {
+        (Some(uid), None) => match current.and_then(|stat| stat.gid) {
+            Some(gid) => (Some(uid), Some(gid)),
+            None => (None, None),
+        },
+        (None, Some(gid)) => match current.and_then(|stat| stat.uid) {
+            Some(uid) => (Some(uid), Some(gid)),
+            None => (None, None),
+        },
+        ids => ids,
+    };
+    FileStat {
+        size: None,
+        uid,
+        gid,
+        perm: Some(options.file_mode as u32),
+        atime: None,
+        mtime: None,
+    }
+}
+
+/// Checks the complete partial file of `remote_path` against `local_hash`,
+/// applies the configured mode and owner and renames it over `remote_path`.
+fn move_into_place(
+    session: &Session,
+    sftp: &Sftp,
+    remote_path: &str,
+    local_hash: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<()> {
+    let remote_file_path = Path::new(remote_path);
+    let partial_path_string = format!("{}{}", remote_path, PARTIAL_SUFFIX);
+    let partial_path = Path::new(&partial_path_string);
+
+    progress.spin("Verifying upload...");
+    let remote_hash = remote_checksum(session, sftp, &partial_path_string, options)?;
+    if remote_hash != local_hash {
+        // A corrupt partial file must not be resumed from on the next attempt
+        let _ = sftp.unlink(partial_path);
+        return Err(anyhow::anyhow!(
+            "{} mismatch after upload: local {} != remote {}",
+            options.checksum.name(),
+            local_hash,
+            remote_hash
+        ));
+    }
+
+    // Applied to the partial file so the final name never appears with the
+    // server's default mode or owner.
+    progress.set_message("Setting file attributes...");
+    let current = if options.uid.is_some() != options.gid.is_some() {
+        sftp.stat(partial_path).ok()
+    } else {
+        None
+    };
+    sftp.setstat(partial_path, attribute_stat(options, current.as_ref()))
+        .context("Failed to set permissions or ownership of the uploaded file")?;
+
+    progress.set_message("Moving file into place...");
+    if options.keep_previous && sftp.stat(remote_file_path).is_ok() {
+        let previous_path = format!("{}{}", remote_path, PREVIOUS_SUFFIX);
+        rename_overwrite(session, sftp, remote_file_path, Path::new(&previous_path))?;
+    }
+    rename_overwrite(session, sftp, partial_path, remote_file_path)?;
+    Ok(())
+}
+
+/// Uploads everything `reader` yields to `remote_path` over an established
+/// session, for input of unknown size such as standard input. With
+/// `compression` the data is compressed on the way: into a ZIP entry named
+/// `entry_name`, or gzip for tar-gz. Returns the number of bytes read.
+///
+/// The input can only be read once, so an upload cannot be resumed or
+/// retried: an existing partial file is overwritten and a failed upload
+/// removes its partial file. The checksum is computed from the bytes sent.
+pub fn upload_stream(
+    session: &Session,
+    reader: impl Read + Send,
+    remote_path: &str,
+    compression: Option<CompressionFormat>,
+    entry_name: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<u64> {
+    progress.spin("Checking remote directory...");
+    let sftp = session.sftp()?;
+    let remote_path = remote::expand_home(session, remote_path)?;
+    if let Some(parent_dir) = Path::new(&remote_path).parent() {
+        if sftp.stat(parent_dir).is_err() {
+            progress.set_message("Creating remote directory...");
+            create_remote_dir_all(&sftp, parent_dir, options.dir_mode)?;
+        }
+    }
 
-    let mut remote_file =
-        sftp.open_mode(remote_file_path, open_flags, 0o644, ssh2::OpenType::File)?;
-    let mut buffer = [0u8; 8192];
-    let mut total_written = start_pos;
+    let partial_path = format!("{}{}", remote_path, PARTIAL_SUFFIX);
+    let result = stream_to_partial(
+        &sftp,
+        reader,
+        Path::new(&partial_path),
+        compression,
+        entry_name,
+        options,
+        progress,
+    )
+    .and_then(|(read, local_hash)| {
+        move_into_place(session, &sftp, &remote_path, &local_hash, options, progress)?;
+        Ok(read)
+    });
+    if result.is_err() {
+        let _ = sftp.unlink(Path::new(&partial_path));
+    }
+    result
+}
 
-    loop {
-        let n = file.read(&mut buffer)?;
-        if n == 0 {
-            break;
+/// Like [`pipelined_copy`], compressing the data on the way with the codec
+/// of `compression`: a ZIP stream with the single entry `entry_name`, or gzip
+/// for tar-gz. Tar has no codec and is refused. Returns the bytes read;
+/// `on_write` counts uncompressed bytes.
+pub fn copy_compressed<R: Read + Send, W: Write>(
+    reader: R,
+    writer: &mut W,
+    compression: Option<CompressionFormat>,
+    entry_name: &str,
+    buffer_size: usize,
+    on_write: impl FnMut(u64),
+) -> Result<u64> {
+    match compression {
+        None => pipelined_copy(reader, writer, buffer_size, on_write),
+        Some(CompressionFormat::Zip) => {
+            let mut zip = zip::ZipWriter::new_stream(writer);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .large_file(true);
+            zip.start_file(entry_name, options)?;
+            let read = pipelined_copy(reader, &mut zip, buffer_size, on_write)?;
+            zip.finish()?;
+            Ok(read)
         }
-        remote_file.write_all(&buffer[..n])?;
-        total_written += n as u64;
-        pb.set_position(total_written);
+        Some(CompressionFormat::TarGz) => {
+            let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+            let read = pipelined_copy(reader, &mut encoder, buffer_size, on_write)?;
+            encoder.finish()?;
+            Ok(read)
+        }
+        Some(CompressionFormat::Tar) => Err(anyhow::anyhow!(
+            "A tar archive needs the size of its input in advance, use zip or tar-gz for streams"
+        )),
     }
+}
+
+/// Writes `reader`, compressed as requested, to `partial_path` and returns
+/// the bytes read and the digest of the bytes written.
+fn stream_to_partial(
+    sftp: &Sftp,
+    reader: impl Read + Send,
+    partial_path: &Path,
+    compression: Option<CompressionFormat>,
+    entry_name: &str,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<(u64, String)> {
+    let mut remote_file = sftp.open_mode(
+        partial_path,
+        open_flags_truncate(),
+        options.file_mode,
+        ssh2::OpenType::File,
+    )?;
+    let writer = ThrottledWriter::new(&mut remote_file, options.throttle.as_ref());
+    let mut writer = HashingWriter::new(options.checksum, writer);
+
+    progress.start_stream();
+    let read = copy_compressed(
+        reader,
+        &mut writer,
+        compression,
+        entry_name,
+        options.buffer_size,
+        |n| progress.advance(n),
+    )?;
+    let local_hash = writer.finish();
+    remote_file.close()?;
+    Ok((read, local_hash))
+}
 
-    pb.finish_with_message("Upload complete");
-    println!("✅ File uploaded successfully to ({}) {}", ip, remote_path);
+/// Writes each of `chunks` of `local_zip` to the same offsets of
+/// `partial_path`, the first over `session` and the others over new sessions
+/// to `target`, all at once. Progress is reported as the combined position
+/// of all chunks only.
+fn upload_chunks(
+    session: &Session,
+    target: &SshTarget,
+    local_zip: &str,
+    partial_path: &Path,
+    chunks: &[Range<u64>],
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> Result<()> {
+    // Created up front so that no chunk truncates another one's data
+    session
+        .sftp()?
+        .open_mode(
+            partial_path,
+            open_flags_truncate(),
+            options.file_mode,
+            ssh2::OpenType::File,
+        )?
+        .close()?;
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                scope.spawn(move || -> Result<()> {
+                    let extra_session;
+                    let session = if index == 0 {
+                        session
+                    } else {
+                        // Quietly, the line belongs to the transfer as a whole
+                        extra_session = open_session_with(target, |_| {})
+                            .context("Failed to open an extra connection")?;
+                        &extra_session
+                    };
+                    upload_range(
+                        session,
+                        local_zip,
+                        partial_path,
+                        chunk.clone(),
+                        options.buffer_size,
+                        options.throttle.as_ref(),
+                        progress,
+                    )
+                    .with_context(|| {
+                        format!("Failed to upload bytes {}..{}", chunk.start, chunk.end)
+                    })
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("upload worker panicked"))
+            .collect::<Result<Vec<()>>>()
+    })?;
+    Ok(())
+}
+
+/// Copies `range` of `local_zip` to the same offset of the existing remote
+/// file `remote_path`.
+fn upload_range(
+    session: &Session,
+    local_zip: &str,
+    remote_path: &Path,
+    range: Range<u64>,
+    buffer_size: usize,
+    throttle: Option<&Throttle>,
+    progress: &TransferProgress,
+) -> Result<()> {
+    let mut file = File::open(local_zip)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let file = file.take(range.end - range.start);
+
+    let sftp = session.sftp()?;
+    let mut remote_file = sftp.open_mode(remote_path, OpenFlags::WRITE, 0, ssh2::OpenType::File)?;
+    remote_file.seek(SeekFrom::Start(range.start))?;
+
+    let mut writer = ThrottledWriter::new(&mut remote_file, throttle);
+    pipelined_copy(file, &mut writer, buffer_size, |n| progress.advance(n))?;
+    remote_file.close()?;
     Ok(())
 }
 
 pub fn upload_via_sftp_with_retry(
-    ip: &str,
-    port: u16,
-    username: &str,
-    password: &str,
+    target: &SshTarget,
+    local_zip: &str,
+    remote_path: &str,
+    max_retries: u32,
+    options: &UploadOptions,
+) -> anyhow::Result<Session> {
+    let progress = TransferProgress::new();
+    let result = upload_with_progress(
+        target,
+        local_zip,
+        remote_path,
+        max_retries,
+        options,
+        &progress,
+    );
+    finish_upload(
+        &progress,
+        result.as_ref().ok().map(|(_, outcome)| *outcome),
+        target,
+        remote_path,
+    );
+    result.map(|(session, _)| session)
+}
+
+/// Like [`upload_via_sftp_with_retry`], reporting on an existing `progress`
+/// display, e.g. one line of a multi-host upload. `progress` is left running
+/// so follow-up steps can report on it; see [`finish_upload`].
+pub fn upload_with_progress(
+    target: &SshTarget,
     local_zip: &str,
     remote_path: &str,
     max_retries: u32,
-) -> anyhow::Result<()> {
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> anyhow::Result<(Session, UploadOutcome)> {
+    let (session, outcomes) = upload_many_with_progress(
+        target,
+        &[(local_zip, remote_path)],
+        max_retries,
+        options,
+        progress,
+    )?;
+    Ok((session, outcomes[0]))
+}
+
+/// Uploads several `(local, remote)` files one after another over a single
+/// session, returning an outcome per file. A failed attempt reconnects and
+/// starts over; files that already arrived are then skipped as identical.
+pub fn upload_many_with_progress(
+    target: &SshTarget,
+    files: &[(&str, &str)],
+    max_retries: u32,
+    options: &UploadOptions,
+    progress: &TransferProgress,
+) -> anyhow::Result<(Session, Vec<UploadOutcome>)> {
     let mut last_err = None;
     for attempt in 1..=max_retries {
-        match upload_via_sftp(ip, port, username, password, local_zip, remote_path) {
-            Ok(()) => return Ok(()),
+        match upload_attempt(target, files, options, progress) {
+            Ok(uploaded) => return Ok(uploaded),
             Err(e) => {
-                eprintln!("❌ Attempt {} failed: {}", attempt, e);
+                progress.println(&format!("❌ Attempt {} failed: {}", attempt, e));
                 last_err = Some(e);
                 if attempt < max_retries {
-                    let retry_spinner = LoadingSpinner::new("Retrying in 2 seconds...");
+                    progress.spin("Retrying in 2 seconds...");
                     thread::sleep(Duration::from_secs(2));
-                    retry_spinner.finish_and_clear();
                 }
             }
         }
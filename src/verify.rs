@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use walkdir::WalkDir;
+
+use crate::compress::CompressionFormat;
+use crate::extract::{self, safe_entry_path, CountingReader, EntryKind};
+use crate::loading::TransferProgress;
+
+/// Files and directories of an archive or source tree by relative path, with
+/// the size of files and `None` for directories.
+pub type TreeListing = BTreeMap<PathBuf, Option<u64>>;
+
+/// Differences between an archive and the tree it was created from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TreeDiff {
+    /// In the source tree but not in the archive.
+    pub missing: Vec<PathBuf>,
+    /// In the archive but not in the source tree.
+    pub unexpected: Vec<PathBuf>,
+    /// Files whose archived size differs, with the archived and source size.
+    pub size_mismatches: Vec<(PathBuf, u64, u64)>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.size_mismatches.is_empty()
+    }
+
+    /// One line per difference, for error messages.
+    pub fn describe(&self) -> String {
+        let missing = self
+            .missing
+            .iter()
+            .map(|path| format!("missing from archive: {}", path.display()));
+        let unexpected = self
+            .unexpected
+            .iter()
+            .map(|path| format!("not in source: {}", path.display()));
+        let sizes = self.size_mismatches.iter().map(|(path, archived, source)| {
+            format!(
+                "size differs: {} ({} bytes archived, {} in source)",
+                path.display(),
+                archived,
+                source
+            )
+        });
+        missing
+            .chain(unexpected)
+            .chain(sizes)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Decodes every entry of `archive`, failing on corrupt data: ZIP entries are
+/// checked against their CRC-32 and gzip streams against their trailer.
+/// Returns the files and directories of the archive. `progress` follows the
+/// bytes read and is left running.
+pub fn verify_archive(
+    archive: &Path,
+    format: CompressionFormat,
+    progress: &TransferProgress,
+) -> Result<TreeListing> {
+    let size = fs::metadata(archive)
+        .with_context(|| format!("Failed to open {}", archive.display()))?
+        .len();
+    let file = CountingReader::new(extract::open_archive(archive)?, size, progress);
+
+    progress.start_transfer(size, 0);
+    let verified = match format {
+        CompressionFormat::Zip => verify_zip(file),
+        CompressionFormat::Tar => verify_tar(file),
+        CompressionFormat::TarGz => {
+            let mut decoder = GzDecoder::new(file);
+            let listing = verify_tar(&mut decoder)?;
+            // tar stops at its end marker; the gzip trailer is only checked
+            // once the stream is read to the end
+            io::copy(&mut decoder, &mut io::sink()).context("Corrupt gzip stream")?;
+            Ok(listing)
+        }
+    };
+    verified.with_context(|| format!("{} is corrupt", archive.display()))
+}
+
+fn verify_zip(file: impl Read + io::Seek) -> Result<TreeListing> {
+    let mut zip = zip::ZipArchive::new(file)?;
+    let mut listing = TreeListing::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        let name = PathBuf::from(entry.name());
+        // Reading to the end makes the zip reader compare the CRC-32
+        let read = io::copy(&mut entry, &mut io::sink())
+            .with_context(|| format!("Failed to decode {}", name.display()))?;
+        if read != entry.size() {
+            return Err(anyhow::anyhow!(
+                "{} decoded to {} bytes, expected {}",
+                name.display(),
+                read,
+                entry.size()
+            ));
+        }
+        record(&mut listing, &name, extract::zip_kind(&entry), read);
+    }
+    Ok(listing)
+}
+
+fn verify_tar(reader: impl Read) -> Result<TreeListing> {
+    let mut archive = tar::Archive::new(reader);
+    let mut listing = TreeListing::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let kind = extract::tar_kind(entry.header().entry_type());
+        let size = entry.header().size()?;
+        let read = io::copy(&mut entry, &mut io::sink())
+            .with_context(|| format!("Failed to decode {}", name.display()))?;
+        if read != size {
+            return Err(anyhow::anyhow!(
+                "{} is truncated: {} of {} bytes",
+                name.display(),
+                read,
+                size
+            ));
+        }
+        record(&mut listing, &name, kind, size);
+    }
+    Ok(listing)
+}
+
+/// Adds an archive entry under its normalized path. Unsafe paths are kept
+/// verbatim so they show up as unexpected.
+fn record(listing: &mut TreeListing, name: &Path, kind: EntryKind, size: u64) {
+    let path = safe_entry_path(name).unwrap_or_else(|| name.to_path_buf());
+    if path.as_os_str().is_empty() {
+        return;
+    }
+    let size = match kind {
+        EntryKind::Dir => None,
+        _ => Some(size),
+    };
+    listing.insert(path, size);
+}
+
+/// Lists `source` the way `compress::compress` archives it: a file under its
+/// own name, a directory by the paths of its contents. Symlinks are listed as
+/// what they point to.
+pub fn source_listing(source: &Path) -> Result<TreeListing> {
+    let mut listing = TreeListing::new();
+    let meta =
+        fs::metadata(source).with_context(|| format!("Failed to read {}", source.display()))?;
+    if meta.is_file() {
+        let name = source
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid source path: {}", source.display()))?;
+        listing.insert(PathBuf::from(name), Some(meta.len()));
+        return Ok(listing);
+    }
+
+    for entry in WalkDir::new(source).min_depth(1) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(source)?.to_path_buf();
+        let meta = fs::metadata(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        if meta.is_dir() {
+            listing.insert(relative, None);
+        } else if meta.is_file() {
+            listing.insert(relative, Some(meta.len()));
+        }
+    }
+    Ok(listing)
+}
+
+/// Lists the inputs of `compress::compress_bundle`, each below its name.
+pub fn bundle_listing(inputs: &[PathBuf], names: &[String]) -> Result<TreeListing> {
+    let mut listing = TreeListing::new();
+    for (input, name) in inputs.iter().zip(names) {
+        let name = Path::new(name);
+        if input.is_dir() {
+            listing.insert(name.to_path_buf(), None);
+            for (path, size) in source_listing(input)? {
+                listing.insert(name.join(path), size);
+            }
+        } else {
+            let size = fs::metadata(input)
+                .with_context(|| format!("Failed to read {}", input.display()))?
+                .len();
+            listing.insert(name.to_path_buf(), Some(size));
+        }
+    }
+    Ok(listing)
+}
+
+/// Compares the listing of an archive with the listing of its source tree.
+pub fn compare_listings(archive: &TreeListing, source: &TreeListing) -> TreeDiff {
+    let mut diff = TreeDiff::default();
+    for (path, source_size) in source {
+        match (archive.get(path), source_size) {
+            (None, _) => diff.missing.push(path.clone()),
+            (Some(Some(archived)), Some(source_size)) if archived != source_size => {
+                diff.size_mismatches
+                    .push((path.clone(), *archived, *source_size));
+            }
+            _ => {}
+        }
+    }
+    diff.unexpected = archive
+        .keys()
+        .filter(|path| !source.contains_key(*path))
+        .cloned()
+        .collect();
+    diff
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flash::checksum::{
+        digest_command, hash_reader, parse_tool_output, ChecksumAlgorithm, HashingWriter,
+    };
+
+    fn hash(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+        hash_reader(algorithm, Cursor::new(data.to_vec()), 8192).unwrap()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            hash(ChecksumAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash(ChecksumAlgorithm::Blake3, b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(hash(ChecksumAlgorithm::Xxh3, b""), "2d06800538d394c2");
+    }
+
+    #[test]
+    fn test_digest_does_not_depend_on_buffer_size() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 253) as u8).collect();
+        for algorithm in [
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Blake3,
+            ChecksumAlgorithm::Xxh3,
+        ] {
+            let small = hash_reader(algorithm, Cursor::new(data.clone()), 7).unwrap();
+            let large = hash_reader(algorithm, Cursor::new(data.clone()), 1 << 20).unwrap();
+            assert_eq!(small, large);
+            assert_eq!(small.len(), algorithm.hex_len());
+        }
+    }
+
+    #[test]
+    fn test_parse_tool_output() {
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            parse_tool_output(ChecksumAlgorithm::Sha256, &format!("{}  /srv/a.zip\n", sha)),
+            Some(sha.to_string())
+        );
+        assert_eq!(
+            parse_tool_output(
+                ChecksumAlgorithm::Xxh3,
+                "XXH3_2D06800538D394C2  /srv/a.zip\n"
+            ),
+            Some("2d06800538d394c2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_tool_output_rejects_garbage() {
+        assert_eq!(parse_tool_output(ChecksumAlgorithm::Sha256, ""), None);
+        assert_eq!(
+            parse_tool_output(ChecksumAlgorithm::Sha256, "This service allows sftp only"),
+            None
+        );
+        // An xxh3 digest is too short for sha256
+        assert_eq!(
+            parse_tool_output(ChecksumAlgorithm::Sha256, "2d06800538d394c2  a"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_digest_command() {
+        assert_eq!(
+            digest_command(ChecksumAlgorithm::Blake3, "/var/log/app.log", None),
+            "b3sum '/var/log/app.log' 2>/dev/null"
+        );
+        assert_eq!(
+            digest_command(ChecksumAlgorithm::Sha256, "a b.log", Some(42)),
+            "head -c 42 'a b.log' | sha256sum 2>/dev/null || \
+             head -c 42 'a b.log' | shasum -a 256 2>/dev/null"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prefix_digest_command_hashes_prefix() {
+        let path = std::env::temp_dir().join(format!("flash-prefix-{}.log", std::process::id()));
+        std::fs::write(&path, b"abcdefgh").unwrap();
+        let command = digest_command(ChecksumAlgorithm::Sha256, &path.to_string_lossy(), Some(3));
+        let output = std::process::Command::new("sh")
+            .args(["-c", &command])
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Hosts without sha256sum or shasum have nothing to compare against
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert_eq!(
+                parse_tool_output(ChecksumAlgorithm::Sha256, &stdout),
+                Some(hash(ChecksumAlgorithm::Sha256, b"abc"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        for algorithm in [
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Blake3,
+            ChecksumAlgorithm::Xxh3,
+        ] {
+            let mut written = Vec::new();
+            let mut writer = HashingWriter::new(algorithm, &mut written);
+            for piece in data.chunks(7_000) {
+                writer.write_all(piece).unwrap();
+            }
+            assert_eq!(writer.finish(), hash(algorithm, &data));
+            assert_eq!(written, data);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+#[cfg(test)]
+mod tests {
+    use flash::cleanup;
+    use std::fs;
+
+    #[test]
+    fn test_unique_temp_dirs() {
+        let first = cleanup::unique_temp_dir().unwrap();
+        let second = cleanup::unique_temp_dir().unwrap();
+        assert_ne!(first, second);
+        assert!(first.is_dir());
+        assert_eq!(fs::read_dir(&first).unwrap().count(), 0);
+
+        fs::write(first.join("archive.zip"), "data").unwrap();
+        cleanup::remove(&first).unwrap();
+        cleanup::remove(&second).unwrap();
+        assert!(!first.exists());
+        assert!(!second.exists());
+    }
+
+    #[test]
+    fn test_remove_missing_path() {
+        let dir = cleanup::unique_temp_dir().unwrap();
+        cleanup::remove(&dir).unwrap();
+        cleanup::remove(&dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+#[cfg(test)]
+mod tests {
+    use flash::compress::CompressionFormat;
+    use flash::config::{
+        interpolate_command, interpolate_env, Config, ServerConfig, EXAMPLE_CONFIG,
+    };
+
+    const GROUP_CONFIG: &str = r#"
+        [servers.web1]
+        name = "Web 1"
+        ip = "10.0.0.11"
+        username = "deploy"
+
+        [servers.web2]
+        name = "Web 2"
+        ip = "10.0.0.12"
+        username = "deploy"
+        remote_path = "/srv/web2"
+        format = "zip"
+
+        [groups.web]
+        servers = ["web1", "web2"]
+        remote_path = "/srv/app"
+        format = "tar-gz"
+        post_upload = ["systemctl restart app"]
+        health_url = "http://{host}:8080/health"
+
+        [groups.broken]
+        servers = ["web1", "missing"]
+    "#;
+
+    #[test]
+    fn test_example_config_parses() {
+        let config: Config = toml::from_str(EXAMPLE_CONFIG).unwrap();
+        assert_eq!(config.get_default_server().unwrap().name, "Home Server");
+        let work = config.get_server("work").unwrap();
+        assert_eq!(work.keep_releases, Some(5));
+        assert_eq!(work.retention.as_ref().unwrap().keep_last, Some(10));
+    }
+
+    #[test]
+    fn test_server_config_defaults() {
+        let server: ServerConfig = toml::from_str(
+            r#"
+            name = "Minimal"
+            ip = "10.0.0.1"
+            username = "deploy"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(server.port(), 22);
+        assert_eq!(server.remote_dir(), "/home/deploy");
+        // Retention never prunes the default home directory
+        assert!(server.retention_dir().is_err());
+        assert!(server.dir_mode.is_none());
+        assert!(server.uid.is_none());
+    }
+
+    #[test]
+    fn test_remote_name_template_prefers_cli() {
+        let mut server: ServerConfig = toml::from_str(
+            r#"
+            name = "Minimal"
+            ip = "10.0.0.1"
+            username = "deploy"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(server.remote_name_template(None), None);
+        server.remote_name = Some("{name}-{date}".to_string());
+        assert_eq!(server.remote_name_template(None), Some("{name}-{date}"));
+        assert_eq!(
+            server.remote_name_template(Some("nightly-{date}")),
+            Some("nightly-{date}")
+        );
+    }
+
+    #[test]
+    fn test_server_config_modes_and_ownership() {
+        let config: Config = toml::from_str(
+            r#"
+            [servers.web]
+            name = "Web"
+            ip = "10.0.0.2"
+            username = "deploy"
+            remote_path = "/opt/releases/app"
+            dir_mode = 0o775
+            file_mode = 0o640
+            uid = 1001
+            gid = 1002
+            "#,
+        )
+        .unwrap();
+        let server = config.get_server("web").unwrap();
+        assert_eq!(server.dir_mode, Some(0o775));
+        assert_eq!(server.file_mode, Some(0o640));
+        assert_eq!(server.uid, Some(1001));
+        assert_eq!(server.gid, Some(1002));
+        assert_eq!(server.remote_dir(), "/opt/releases/app");
+        assert_eq!(server.retention_dir().unwrap(), "/opt/releases/app");
+    }
+
+    #[test]
+    fn test_resolve_server_by_name() {
+        let config: Config = toml::from_str(GROUP_CONFIG).unwrap();
+        let servers = config.resolve("web1").unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "Web 1");
+        assert!(servers[0].format.is_none());
+    }
+
+    #[test]
+    fn test_resolve_group_inherits_defaults() {
+        let config: Config = toml::from_str(GROUP_CONFIG).unwrap();
+        let servers = config.resolve("web").unwrap();
+        assert_eq!(servers.len(), 2);
+
+        assert_eq!(servers[0].remote_dir(), "/srv/app");
+        assert_eq!(servers[0].format, Some(CompressionFormat::TarGz));
+        assert_eq!(
+            servers[0].post_upload.as_deref(),
+            Some(&["systemctl restart app".to_string()][..])
+        );
+
+        // Values set on the server win over the group's
+        assert_eq!(servers[1].remote_dir(), "/srv/web2");
+        assert_eq!(servers[1].format, Some(CompressionFormat::Zip));
+        assert_eq!(
+            servers[1].health_url.as_deref(),
+            Some("http://{host}:8080/health")
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown_names() {
+        let config: Config = toml::from_str(GROUP_CONFIG).unwrap();
+        assert!(config.resolve("nope").is_err());
+        assert!(config.resolve("broken").is_err());
+    }
+
+    #[test]
+    fn test_listing_is_sorted() {
+        let config: Config = toml::from_str(GROUP_CONFIG).unwrap();
+        let servers: Vec<&String> = config.list_servers().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(servers, ["web1", "web2"]);
+        let groups: Vec<&String> = config.list_groups().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(groups, ["broken", "web"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = toml::from_str(EXAMPLE_CONFIG).unwrap();
+        config.validate().unwrap();
+
+        let config: Config = toml::from_str(GROUP_CONFIG).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("'missing' of group 'broken'"));
+
+        let config: Config = toml::from_str(
+            r#"
+            [servers.web1]
+            name = "Web 1"
+            ip = "10.0.0.11"
+            username = "deploy"
+
+            [default]
+            server = "web"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+            [servers.web1]
+            name = "Web 1"
+            ip = "10.0.0.11"
+            username = "deploy"
+
+            [groups.empty]
+            servers = []
+            "#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("Group 'empty' has no servers"));
+        assert!(config.resolve("empty").is_err());
+    }
+
+    fn test_env(name: &str) -> Option<String> {
+        match name {
+            "DEPLOY_HOST" => Some("10.0.0.7".to_string()),
+            "HOME" => Some("/home/me".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let expand = |value: &str| interpolate_env(value, &test_env);
+        assert_eq!(expand("${HOME}/drop").unwrap(), "/home/me/drop");
+        assert_eq!(expand("${MISSING:-/srv}/app").unwrap(), "/srv/app");
+        assert_eq!(expand("${EMPTY:-fallback}").unwrap(), "fallback");
+        assert_eq!(expand("${EMPTY}").unwrap(), "");
+        assert_eq!(expand("$HOME $1 $${HOME}").unwrap(), "$HOME $1 ${HOME}");
+
+        assert!(expand("${MISSING}").is_err());
+        assert!(expand("${HOME").is_err());
+        assert!(expand("${1X}").is_err());
+    }
+
+    #[test]
+    fn test_config_interpolation() {
+        let config = Config::from_toml(
+            r#"
+            [servers.web]
+            name = "Web"
+            ip = "${DEPLOY_HOST}"
+            username = "${DEPLOY_USER:-deploy}"
+            remote_path = "~/uploads"
+            post_upload = ["ls ${HOME}"]
+
+            [servers.db]
+            name = "DB"
+            ip = "${DEPLOY_HOST}"
+            username = "db"
+            remote_path = "${HOME}/${DEPLOY_HOST}"
+            "#,
+            &test_env,
+        )
+        .unwrap();
+        let web = config.get_server("web").unwrap();
+        assert_eq!(web.ip, "10.0.0.7");
+        assert_eq!(web.username, "deploy");
+        // `~` is expanded on the server
+        assert_eq!(web.remote_dir(), "~/uploads");
+        assert_eq!(web.post_upload.as_ref().unwrap()[0], "ls /home/me");
+        // So is a leading `${HOME}` in a remote path
+        let db = config.get_server("db").unwrap();
+        assert_eq!(db.remote_dir(), "${HOME}/10.0.0.7");
+
+        let err = Config::from_toml(
+            "[servers.web]\nname = \"Web\"\nip = \"${UNSET_HOST}\"\nusername = \"u\"",
+            &test_env,
+        )
+        .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("servers.web.ip"));
+        assert!(message.contains("UNSET_HOST"));
+    }
+
+    #[test]
+    fn test_interpolate_command_keeps_shell_variables() {
+        let expand = |value: &str| interpolate_command(value, &test_env);
+        assert_eq!(expand("ls ${HOME}"), "ls /home/me");
+        assert_eq!(
+            expand("sign.sh \"${FLASH_ARCHIVE}\" ${MISSING:-x}"),
+            "sign.sh \"${FLASH_ARCHIVE}\" x"
+        );
+        assert_eq!(
+            expand("echo ${#HOME} ${HOME%/*}"),
+            "echo ${#HOME} ${HOME%/*}"
+        );
+        assert_eq!(expand("echo ${unclosed"), "echo ${unclosed");
+    }
+
+    #[test]
+    fn test_config_hooks_keep_unset_variables() {
+        let config = Config::from_toml(
+            r#"
+            [servers.web]
+            name = "Web"
+            ip = "${DEPLOY_HOST}"
+            username = "deploy"
+            pre_upload = ["sign.sh \"${FLASH_ARCHIVE}\""]
+            post_deploy = ["cd ${RELEASE_DIR} && ls ${HOME}"]
+            health_check = "test -f ${PID_FILE}"
+
+            [groups.all]
+            servers = ["web"]
+            post_upload = ["echo ${FLASH_REMOTE_PATH}"]
+            "#,
+            &test_env,
+        )
+        .unwrap();
+        let web = config.get_server("web").unwrap();
+        assert_eq!(
+            web.pre_upload.as_ref().unwrap()[0],
+            "sign.sh \"${FLASH_ARCHIVE}\""
+        );
+        assert_eq!(
+            web.post_deploy.as_ref().unwrap()[0],
+            "cd ${RELEASE_DIR} && ls /home/me"
+        );
+        assert_eq!(web.health_check.as_deref(), Some("test -f ${PID_FILE}"));
+        let group = config.get_group("all").unwrap();
+        assert_eq!(
+            group.post_upload.as_ref().unwrap()[0],
+            "echo ${FLASH_REMOTE_PATH}"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use flash::compress::CompressionFormat;
+    use flash::deploy::{self, ReleaseLayout};
+
+    fn releases(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_release_layout_paths() {
+        let layout = ReleaseLayout::new("/srv/app/");
+        assert_eq!(layout.releases_dir(), "/srv/app/releases");
+        assert_eq!(
+            layout.release_dir("20261019120000"),
+            "/srv/app/releases/20261019120000"
+        );
+        assert_eq!(
+            layout.release_archive("20261019120000", CompressionFormat::TarGz),
+            "/srv/app/releases/20261019120000.tar.gz"
+        );
+        assert_eq!(layout.current_link(), "/srv/app/current");
+    }
+
+    #[test]
+    fn test_release_name_is_timestamp() {
+        let name = deploy::release_name();
+        assert_eq!(name.len(), 14);
+        assert!(name.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_release_candidates_sort_between_timestamps() {
+        let candidates: Vec<String> = deploy::release_candidates("20261019120000").collect();
+        assert_eq!(candidates[0], "20261019120000");
+        assert_eq!(candidates[1], "20261019120000-02");
+        assert_eq!(candidates.last().unwrap(), "20261019120000-99");
+        let mut sorted = candidates.clone();
+        sorted.push("20261019120001".to_string());
+        sorted.push("20261019115959".to_string());
+        sorted.sort();
+        assert_eq!(sorted[0], "20261019115959");
+        assert_eq!(&sorted[1..sorted.len() - 1], &candidates[..]);
+        assert_eq!(sorted.last().unwrap(), "20261019120001");
+    }
+
+    #[test]
+    fn test_discard_on_error_cleans_up_failed_release() {
+        let discarded = Cell::new(false);
+        let result: anyhow::Result<()> = Err(anyhow::anyhow!("extraction failed"));
+        let result = deploy::discard_on_error(result, || {
+            discarded.set(true);
+            Err(anyhow::anyhow!("rm failed"))
+        });
+        assert!(discarded.get());
+        assert_eq!(result.unwrap_err().to_string(), "extraction failed");
+    }
+
+    #[test]
+    fn test_discard_on_error_keeps_activated_release() {
+        let discarded = Cell::new(false);
+        let result = deploy::discard_on_error(Ok("20261019120000"), || {
+            discarded.set(true);
+            Ok(())
+        });
+        assert!(!discarded.get());
+        assert_eq!(result.unwrap(), "20261019120000");
+    }
+
+    #[test]
+    fn test_releases_to_remove_keeps_newest() {
+        let all = releases(&["20260101", "20260102", "20260103", "20260104"]);
+        assert_eq!(
+            deploy::releases_to_remove(&all, Some("20260104"), 2),
+            releases(&["20260101", "20260102"])
+        );
+        assert!(deploy::releases_to_remove(&all, None, 10).is_empty());
+    }
+
+    #[test]
+    fn test_releases_to_remove_never_removes_current() {
+        let all = releases(&["20260101", "20260102", "20260103"]);
+        assert_eq!(
+            deploy::releases_to_remove(&all, Some("20260101"), 1),
+            releases(&["20260102"])
+        );
+    }
+
+    #[test]
+    fn test_rollback_target_previous_release() {
+        let all = releases(&["20260101", "20260102", "20260103"]);
+        assert_eq!(
+            deploy::rollback_target(&all, Some("20260103"), None).unwrap(),
+            "20260102"
+        );
+    }
+
+    #[test]
+    fn test_rollback_target_explicit_release() {
+        let all = releases(&["20260101", "20260102", "20260103"]);
+        assert_eq!(
+            deploy::rollback_target(&all, Some("20260103"), Some("20260101")).unwrap(),
+            "20260101"
+        );
+        assert!(deploy::rollback_target(&all, Some("20260103"), Some("20250101")).is_err());
+    }
+
+    #[test]
+    fn test_rollback_target_without_older_release() {
+        let all = releases(&["20260101"]);
+        assert!(deploy::rollback_target(&all, Some("20260101"), None).is_err());
+        assert!(deploy::rollback_target(&all, None, None).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod tests {
+    use flash::compress::CompressionFormat;
+    use flash::download::{partial_path, tar_operands};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_tar_operands() {
+        let operands = tar_operands;
+        assert_eq!(
+            operands("/var/log/app"),
+            ("/var/log".to_string(), "app".to_string())
+        );
+        assert_eq!(
+            operands("/var/log/app/"),
+            ("/var/log".to_string(), "app".to_string())
+        );
+        assert_eq!(operands("/var"), ("/".to_string(), "var".to_string()));
+        assert_eq!(operands("/"), ("/".to_string(), ".".to_string()));
+        assert_eq!(operands("logs"), (".".to_string(), "logs".to_string()));
+    }
+
+    #[test]
+    fn test_partial_path() {
+        assert_eq!(
+            partial_path(Path::new("out/app.tar.gz")),
+            PathBuf::from("out/app.tar.gz.flash-partial")
+        );
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        let format = |path: &str| CompressionFormat::from_path(Path::new(path));
+        assert_eq!(format("app.tar.gz"), Some(CompressionFormat::TarGz));
+        assert_eq!(format("app.TGZ"), Some(CompressionFormat::TarGz));
+        assert_eq!(format("app.tar"), Some(CompressionFormat::Tar));
+        assert_eq!(format("app.zip"), Some(CompressionFormat::Zip));
+        assert_eq!(format("app.log"), None);
+    }
+}
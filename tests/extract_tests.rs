@@ -0,0 +1,259 @@
+#[cfg(test)]
+mod tests {
+    use flash::compress::{self, CompressionFormat};
+    use flash::extract::{self, safe_entry_path, EntryKind};
+    use flash::loading::TransferProgress;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flash-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn extract(archive: &Path, format: CompressionFormat, dest: &Path) -> anyhow::Result<()> {
+        let progress = TransferProgress::new();
+        let result = extract::extract_archive(archive, format, dest, &progress);
+        progress.finish_and_clear();
+        result.map(|_| ())
+    }
+
+    #[test]
+    fn test_safe_entry_path() {
+        assert_eq!(
+            safe_entry_path(Path::new("./app/bin/run")),
+            Some(PathBuf::from("app/bin/run"))
+        );
+        assert_eq!(safe_entry_path(Path::new("./")), Some(PathBuf::new()));
+        assert_eq!(safe_entry_path(Path::new("../etc/passwd")), None);
+        assert_eq!(safe_entry_path(Path::new("app/../../evil")), None);
+        assert_eq!(safe_entry_path(Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn test_extract_round_trip() {
+        let base = scratch_dir("extract");
+        let source = base.join("source");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::write(source.join("a.txt"), "abc").unwrap();
+        fs::write(source.join("nested/b.txt"), "def").unwrap();
+
+        for format in [
+            CompressionFormat::Zip,
+            CompressionFormat::Tar,
+            CompressionFormat::TarGz,
+        ] {
+            let archive = base.join(format!("source.{}", format.extension()));
+            let dest = base.join(format!("dest-{}", format.name()));
+            compress::compress(
+                &source.to_string_lossy(),
+                &archive.to_string_lossy(),
+                format,
+            )
+            .unwrap();
+            extract(&archive, format, &dest).unwrap();
+            assert_eq!(fs::read_to_string(dest.join("a.txt")).unwrap(), "abc");
+            assert_eq!(
+                fs::read_to_string(dest.join("nested/b.txt")).unwrap(),
+                "def"
+            );
+
+            let entries = extract::list_archive(&archive, format).unwrap();
+            let file = entries
+                .iter()
+                .find(|entry| entry.path.ends_with("nested/b.txt"))
+                .unwrap();
+            assert_eq!(file.kind, EntryKind::File);
+            assert_eq!(file.size, 3);
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_zip_slip_is_refused() {
+        let base = scratch_dir("zip-slip");
+        let archive = base.join("evil.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("../evil.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"pwned").unwrap();
+        zip.finish().unwrap();
+
+        let dest = base.join("dest");
+        let err = extract(&archive, CompressionFormat::Zip, &dest).unwrap_err();
+        assert!(format!("{:#}", err).contains("leaves the destination"));
+        assert!(!base.join("evil.txt").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_tar_slip_is_refused() {
+        let base = scratch_dir("tar-slip");
+        let archive = base.join("evil.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        // set_path refuses `..`, so the name is written directly
+        header.as_gnu_mut().unwrap().name[..12].copy_from_slice(b"../evil.txt\0");
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &b"pwned"[..]).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let dest = base.join("dest");
+        assert!(extract(&archive, CompressionFormat::Tar, &dest).is_err());
+        assert!(!base.join("evil.txt").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = scratch_dir("permissions");
+        let source = base.join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(source.join("run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+
+        for format in [CompressionFormat::Zip, CompressionFormat::TarGz] {
+            let archive = base.join(format!("source.{}", format.extension()));
+            let dest = base.join(format!("dest-{}", format.name()));
+            compress::compress(
+                &source.to_string_lossy(),
+                &archive.to_string_lossy(),
+                format,
+            )
+            .unwrap();
+            extract(&archive, format, &dest).unwrap();
+            let mode = fs::metadata(dest.join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o750, "{}", format.name());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_drops_setuid_and_setgid() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = scratch_dir("setuid");
+        let tar_archive = base.join("setuid.tar");
+        let mut builder = tar::Builder::new(File::create(&tar_archive).unwrap());
+        let mut dir = tar::Header::new_gnu();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_size(0);
+        dir.set_mode(0o2755);
+        builder.append_data(&mut dir, "bin", &[][..]).unwrap();
+        let mut file = tar::Header::new_gnu();
+        file.set_size(0);
+        file.set_mode(0o4755);
+        builder.append_data(&mut file, "bin/run", &[][..]).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        // The zip writer masks permissions, so the setuid bit is patched into
+        // the external attributes of the first central directory entry
+        let zip_archive = base.join("setuid.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default().unix_permissions(0o755);
+        zip.start_file("bin/run", options).unwrap();
+        zip.add_directory("bin/", options).unwrap();
+        zip.finish().unwrap();
+        let mut bytes = fs::read(&zip_archive).unwrap();
+        let central = bytes.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        bytes[central + 38..central + 42].copy_from_slice(&(0o104755u32 << 16).to_le_bytes());
+        fs::write(&zip_archive, bytes).unwrap();
+
+        for (archive, format) in [
+            (tar_archive, CompressionFormat::Tar),
+            (zip_archive, CompressionFormat::Zip),
+        ] {
+            let dest = base.join(format!("dest-{}", format.name()));
+            extract(&archive, format, &dest).unwrap();
+            for path in ["bin", "bin/run"] {
+                let mode = fs::metadata(dest.join(path)).unwrap().permissions().mode();
+                assert_eq!(mode & 0o7777, 0o755, "{} {}", format.name(), path);
+            }
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    /// Asserts that extracting `archive` failed without touching `outside`,
+    /// which the archive's `link` entry points at.
+    #[cfg(unix)]
+    fn assert_outside_untouched(result: anyhow::Result<()>, outside: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let err = result.unwrap_err();
+        assert!(format!("{:#}", err).contains("leaves the destination"));
+        assert!(!outside.join("x").exists());
+        let mode = fs::metadata(outside).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[cfg(unix)]
+    fn outside_dir(base: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let outside = base.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::set_permissions(&outside, fs::Permissions::from_mode(0o700)).unwrap();
+        outside
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_directory_through_symlink_is_refused() {
+        for dir_name in ["link/x", "link"] {
+            let base = scratch_dir(&format!("tar-link-dir-{}", dir_name.len()));
+            let outside = outside_dir(&base);
+            let archive = base.join("evil.tar");
+            let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+            let mut link = tar::Header::new_gnu();
+            link.set_entry_type(tar::EntryType::Symlink);
+            link.set_size(0);
+            builder.append_link(&mut link, "link", &outside).unwrap();
+            let mut dir = tar::Header::new_gnu();
+            dir.set_entry_type(tar::EntryType::Directory);
+            dir.set_size(0);
+            dir.set_mode(0o777);
+            builder.append_data(&mut dir, dir_name, &[][..]).unwrap();
+            builder.finish().unwrap();
+            drop(builder);
+
+            let dest = base.join("dest");
+            assert_outside_untouched(extract(&archive, CompressionFormat::Tar, &dest), &outside);
+            fs::remove_dir_all(&base).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_zip_directory_through_symlink_is_refused() {
+        for dir_name in ["link/x/", "link/"] {
+            let base = scratch_dir(&format!("zip-link-dir-{}", dir_name.len()));
+            let outside = outside_dir(&base);
+            let archive = base.join("evil.zip");
+            let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.add_symlink("link", outside.to_string_lossy(), options)
+                .unwrap();
+            zip.add_directory(dir_name, options.unix_permissions(0o777))
+                .unwrap();
+            zip.finish().unwrap();
+
+            let dest = base.join("dest");
+            assert_outside_untouched(extract(&archive, CompressionFormat::Zip, &dest), &outside);
+            fs::remove_dir_all(&base).unwrap();
+        }
+    }
+}
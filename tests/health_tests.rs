@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use flash::health::{health_url, http_probe, http_status, parse_http_url, retry_until};
+
+    /// Serves one request with `status_line` and returns the URL to probe.
+    fn serve_once(status_line: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let response = format!("{}\r\nContent-Length: 2\r\n\r\nok", status_line);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://127.0.0.1:{}/health", port)
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://10.0.0.5:8080/health").unwrap(),
+            ("10.0.0.5".to_string(), 8080, "/health".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://example.com").unwrap(),
+            ("example.com".to_string(), 80, "/".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://[::1]:9000/up?full=1").unwrap(),
+            ("::1".to_string(), 9000, "/up?full=1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_health_url_brackets_ipv6() {
+        let template = "http://{host}:8080/health";
+        assert_eq!(
+            health_url(template, "10.0.0.5"),
+            "http://10.0.0.5:8080/health"
+        );
+        assert_eq!(
+            health_url(template, "fd00::5"),
+            "http://[fd00::5]:8080/health"
+        );
+        assert_eq!(
+            health_url(template, "[fd00::5]"),
+            "http://[fd00::5]:8080/health"
+        );
+        assert_eq!(
+            parse_http_url(&health_url(template, "fd00::5")).unwrap(),
+            ("fd00::5".to_string(), 8080, "/health".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_http_url_rejects_invalid() {
+        assert!(parse_http_url("https://example.com/health").is_err());
+        assert!(parse_http_url("http://:8080/").is_err());
+        assert!(parse_http_url("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_probe_healthy() {
+        let url = serve_once("HTTP/1.1 200 OK");
+        assert_eq!(http_status(&url, Duration::from_secs(5)).unwrap(), 200);
+
+        let url = serve_once("HTTP/1.1 302 Found");
+        assert!(http_probe(&url, Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_probe_unhealthy() {
+        let url = serve_once("HTTP/1.1 503 Service Unavailable");
+        let err = http_probe(&url, Duration::from_secs(5)).unwrap_err();
+        assert!(err.to_string().contains("503"));
+    }
+
+    #[test]
+    fn test_probe_connection_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let url = format!("http://127.0.0.1:{}/", port);
+        assert!(http_probe(&url, Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn test_retry_until_succeeds_eventually() {
+        let attempts = Cell::new(0);
+        retry_until(Duration::from_secs(5), Duration::from_millis(10), || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(anyhow::anyhow!("not yet"))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn test_retry_until_times_out() {
+        let err = retry_until(Duration::from_millis(50), Duration::from_millis(10), || {
+            Err(anyhow::anyhow!("down"))
+        })
+        .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "down");
+    }
+}
@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use flash::hooks::{self, HookContext};
+
+    fn context() -> HookContext {
+        HookContext {
+            remote_file: "/srv/uploads/app.tar.gz".to_string(),
+            remote_dir: "/srv/uploads".to_string(),
+            archive: "app.tar.gz".to_string(),
+            format: "tar-gz".to_string(),
+            release: "20261019120000".to_string(),
+            release_dir: "/srv/app/releases/20261019120000".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        let expanded = context().expand("tar -xzf {remote_file} -C {remote_dir}/app");
+        assert_eq!(
+            expanded,
+            "tar -xzf /srv/uploads/app.tar.gz -C /srv/uploads/app"
+        );
+
+        let expanded = context().expand("echo {archive} {format}");
+        assert_eq!(expanded, "echo app.tar.gz tar-gz");
+    }
+
+    #[test]
+    fn test_expand_release_placeholders() {
+        let expanded = context().expand("ln -s {release_dir}/public /var/www # {release}");
+        assert_eq!(
+            expanded,
+            "ln -s /srv/app/releases/20261019120000/public /var/www # 20261019120000"
+        );
+    }
+
+    #[test]
+    fn test_expand_leaves_unknown_placeholders() {
+        let expanded = context().expand("systemctl restart {service}");
+        assert_eq!(expanded, "systemctl restart {service}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_hooks_receive_env() {
+        let marker = "local_hook_env.txt";
+        let hooks = vec![format!("printf %s \"$FLASH_SERVER\" > {}", marker)];
+        hooks::run_local_hooks(&hooks, &[("FLASH_SERVER", "web1".to_string())]).unwrap();
+        assert_eq!(std::fs::read_to_string(marker).unwrap(), "web1");
+        std::fs::remove_file(marker).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_hooks_stop_on_failure() {
+        let marker = "local_hook_not_run.txt";
+        let hooks = vec!["exit 3".to_string(), format!("touch {}", marker)];
+        assert!(hooks::run_local_hooks(&hooks, &[]).is_err());
+        assert!(!std::path::Path::new(marker).exists());
+    }
+}
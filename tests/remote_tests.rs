@@ -0,0 +1,28 @@
+#[cfg(test)]
+mod tests {
+    use flash::remote::{home_relative, shell_quote};
+
+    #[test]
+    fn test_shell_quote_plain() {
+        assert_eq!(shell_quote("/srv/app"), "'/srv/app'");
+        assert_eq!(shell_quote("my dir/file.zip"), "'my dir/file.zip'");
+    }
+
+    #[test]
+    fn test_shell_quote_embedded_quote() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_home_relative() {
+        assert_eq!(home_relative("~"), Some(""));
+        assert_eq!(home_relative("~/drop"), Some("/drop"));
+        assert_eq!(home_relative("$HOME/drop"), Some("/drop"));
+        assert_eq!(home_relative("${HOME}/drop"), Some("/drop"));
+        assert_eq!(home_relative("${HOME}"), Some(""));
+        assert_eq!(home_relative("~bob/drop"), None);
+        assert_eq!(home_relative("$HOMEDIR/drop"), None);
+        assert_eq!(home_relative("/srv/$HOME"), None);
+    }
+}
@@ -0,0 +1,121 @@
+#[cfg(test)]
+mod tests {
+    use flash::config::RetentionPolicy;
+    use flash::retention::{self, RemoteArchive};
+
+    const DAY: u64 = 24 * 60 * 60;
+    const NOW: u64 = 100 * DAY;
+
+    fn archive(name: &str, size: u64, age_days: u64) -> RemoteArchive {
+        RemoteArchive {
+            name: name.to_string(),
+            size,
+            mtime: NOW - age_days * DAY,
+        }
+    }
+
+    fn names(archives: &[RemoteArchive]) -> Vec<&str> {
+        archives.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    fn sample() -> Vec<RemoteArchive> {
+        vec![
+            archive("a.zip", 10, 40),
+            archive("b.zip", 10, 20),
+            archive("c.zip", 10, 10),
+            archive("d.zip", 10, 1),
+        ]
+    }
+
+    #[test]
+    fn test_is_archive_name() {
+        assert!(retention::is_archive_name("app.zip"));
+        assert!(retention::is_archive_name("app.tar.gz"));
+        assert!(!retention::is_archive_name("app.zip.flash-partial"));
+        assert!(!retention::is_archive_name("app.zip.prev"));
+        assert!(!retention::is_archive_name("notes.txt"));
+    }
+
+    #[test]
+    fn test_matches_remote_name() {
+        let template = Some("app-{date:%Y%m%d}-{git_sha}");
+        assert!(retention::matches_remote_name(
+            "app-20261019-1a2b3c.tar.gz",
+            template
+        ));
+        assert!(retention::matches_remote_name("app-1-2.zip", template));
+        assert!(!retention::matches_remote_name("app--2.zip", template));
+        assert!(!retention::matches_remote_name("backup.tar.gz", template));
+        assert!(!retention::matches_remote_name("app-1-2.txt", template));
+        assert!(!retention::matches_remote_name(
+            "app-1-2.zip.flash-partial",
+            template
+        ));
+
+        assert!(retention::matches_remote_name(
+            "nightly.zip",
+            Some("nightly.{ext}")
+        ));
+        assert!(retention::matches_remote_name(
+            "nightly.zip",
+            Some("nightly")
+        ));
+        assert!(!retention::matches_remote_name(
+            "other.zip",
+            Some("nightly")
+        ));
+
+        // Without a template any archive is one flash may have uploaded
+        assert!(retention::matches_remote_name("other.zip", None));
+        assert!(!retention::matches_remote_name("notes.txt", None));
+    }
+
+    #[test]
+    fn test_keep_last() {
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..RetentionPolicy::default()
+        };
+        let removed = retention::select_for_removal(&sample(), &policy, NOW, &[]).unwrap();
+        assert_eq!(names(&removed), vec!["b.zip", "a.zip"]);
+    }
+
+    #[test]
+    fn test_max_age() {
+        let policy = RetentionPolicy {
+            max_age: Some("15d".to_string()),
+            ..RetentionPolicy::default()
+        };
+        let removed = retention::select_for_removal(&sample(), &policy, NOW, &[]).unwrap();
+        assert_eq!(names(&removed), vec!["b.zip", "a.zip"]);
+    }
+
+    #[test]
+    fn test_max_total_size() {
+        let policy = RetentionPolicy {
+            max_total_size: Some("25".to_string()),
+            ..RetentionPolicy::default()
+        };
+        let removed = retention::select_for_removal(&sample(), &policy, NOW, &[]).unwrap();
+        assert_eq!(names(&removed), vec!["b.zip", "a.zip"]);
+    }
+
+    #[test]
+    fn test_protected_archive_is_kept() {
+        let policy = RetentionPolicy {
+            max_age: Some("1d".to_string()),
+            ..RetentionPolicy::default()
+        };
+        let removed = retention::select_for_removal(&sample(), &policy, NOW, &["a.zip"]).unwrap();
+        assert_eq!(names(&removed), vec!["c.zip", "b.zip"]);
+    }
+
+    #[test]
+    fn test_invalid_policy_is_rejected() {
+        let policy = RetentionPolicy {
+            max_age: Some("soon".to_string()),
+            ..RetentionPolicy::default()
+        };
+        assert!(retention::select_for_removal(&sample(), &policy, NOW, &[]).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod tests {
+    use flash::sources::{self, glob_match};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flash-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "app.log"));
+        assert!(glob_match("*.log", ".log"));
+        assert!(!glob_match("*.log", "app.log.1"));
+        assert!(glob_match("app-?.log", "app-1.log"));
+        assert!(!glob_match("app-?.log", "app-10.log"));
+        assert!(glob_match("*-*-*.tar.gz", "a-b-c.tar.gz"));
+        assert!(glob_match("[ab]*", "beta"));
+        assert!(!glob_match("[!ab]*", "beta"));
+        assert!(glob_match("v[0-9].txt", "v7.txt"));
+        assert!(!glob_match("v[0-9].txt", "vx.txt"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = scratch_dir("sources");
+        fs::create_dir_all(dir.join("logs/old")).unwrap();
+        fs::create_dir_all(dir.join("dist")).unwrap();
+        for file in [
+            "logs/b.log",
+            "logs/a.log",
+            "logs/.hidden.log",
+            "logs/notes.txt",
+            "logs/old/c.log",
+            "README.md",
+        ] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        let base = dir.to_string_lossy();
+
+        let inputs = sources::expand(&[
+            format!("{}/dist", base),
+            format!("{}/README.md", base),
+            format!("{}/logs/*.log", base),
+            format!("{}/logs/a.log", base),
+        ])
+        .unwrap();
+        assert_eq!(
+            inputs,
+            [
+                dir.join("dist"),
+                dir.join("README.md"),
+                dir.join("logs/a.log"),
+                dir.join("logs/b.log"),
+            ]
+        );
+
+        let recursive = sources::expand(&[format!("{}/**/*.log", base)]).unwrap();
+        assert_eq!(
+            recursive,
+            [
+                dir.join("logs/a.log"),
+                dir.join("logs/b.log"),
+                dir.join("logs/old/c.log"),
+            ]
+        );
+
+        assert!(sources::expand(&[format!("{}/*.missing", base)]).is_err());
+        assert!(sources::expand(&[format!("{}/missing", base)]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entry_names_and_common_dir() {
+        let dir = scratch_dir("sources-names");
+        fs::create_dir_all(dir.join("app/dist")).unwrap();
+        fs::create_dir_all(dir.join("app/conf")).unwrap();
+        fs::write(dir.join("app/README.md"), "").unwrap();
+        fs::write(dir.join("app/conf/README.md"), "").unwrap();
+
+        let inputs = vec![dir.join("app/dist/"), dir.join("app/README.md")];
+        assert_eq!(
+            sources::entry_names(&inputs).unwrap(),
+            ["dist", "README.md"]
+        );
+        assert_eq!(
+            sources::common_dir(&inputs).unwrap(),
+            fs::canonicalize(dir.join("app")).unwrap()
+        );
+        let nested = vec![dir.join("app/conf/README.md"), dir.join("app/dist")];
+        assert_eq!(
+            sources::common_dir(&nested).unwrap(),
+            fs::canonicalize(dir.join("app")).unwrap()
+        );
+
+        let clash = vec![dir.join("app/README.md"), dir.join("app/conf/README.md")];
+        assert!(sources::entry_names(&clash).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+    use flash::compress::CompressionFormat;
+    use flash::template::{render, split_extension, RemoteNameContext};
+    use std::path::{Path, PathBuf};
+
+    fn context(name: &str, ext: &str) -> RemoteNameContext {
+        RemoteNameContext {
+            name: name.to_string(),
+            ext: ext.to_string(),
+            source_dir: PathBuf::from("."),
+            now: Local.with_ymd_and_hms(2024, 3, 7, 9, 5, 2).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = render("{a}-{b:x}-{{lit}}", |key, arg| {
+            Ok(format!("{}{}", key, arg.unwrap_or("")))
+        })
+        .unwrap();
+        assert_eq!(rendered, "a-bx-{lit}");
+
+        assert!(render("{a", |_, _| Ok(String::new())).is_err());
+        assert!(render("a}", |_, _| Ok(String::new())).is_err());
+    }
+
+    #[test]
+    fn test_split_extension() {
+        assert_eq!(split_extension("app.tar.gz"), ("app", "tar.gz"));
+        assert_eq!(split_extension("app_1.2.deb"), ("app_1.2", "deb"));
+        assert_eq!(split_extension("README"), ("README", ""));
+        assert_eq!(split_extension(".env"), (".env", ""));
+    }
+
+    #[test]
+    fn test_remote_name() {
+        let ctx = context("dist", "tar.gz");
+        assert_eq!(
+            ctx.remote_name("{name}-{date:%Y%m%d}").unwrap(),
+            "dist-20240307.tar.gz"
+        );
+        assert_eq!(
+            ctx.remote_name("{name}_{date}_{time}").unwrap(),
+            "dist_2024-03-07_090502.tar.gz"
+        );
+        assert_eq!(ctx.remote_name("latest.{ext}").unwrap(), "latest.tar.gz");
+
+        assert!(ctx.remote_name("{unknown}").is_err());
+        assert!(ctx.remote_name("sub/{name}").is_err());
+        assert!(ctx.remote_name("").is_err());
+    }
+
+    #[test]
+    fn test_contexts() {
+        let ctx = RemoteNameContext::for_archive(Path::new("src"), CompressionFormat::TarGz);
+        assert_eq!(ctx.name, "src");
+        assert_eq!(ctx.ext, "tar.gz");
+        assert_eq!(ctx.source_dir, PathBuf::from("src"));
+
+        // Tests run in the crate root
+        let ctx = RemoteNameContext::for_archive(Path::new("."), CompressionFormat::Zip);
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR")).file_name().unwrap();
+        assert_eq!(ctx.name, crate_dir.to_string_lossy());
+        assert_eq!(ctx.source_dir, PathBuf::from("."));
+
+        let ctx = RemoteNameContext::for_file(Path::new("Cargo.toml"));
+        assert_eq!(ctx.name, "Cargo");
+        assert_eq!(ctx.ext, "toml");
+        assert_eq!(ctx.source_dir, PathBuf::from("."));
+        // The version comes from this crate's manifest
+        assert_eq!(
+            ctx.remote_name("{name}-{version}").unwrap(),
+            format!("Cargo-{}.toml", env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
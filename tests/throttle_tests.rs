@@ -0,0 +1,123 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use flash::throttle::{parse_rate, Throttle, ThrottledWriter, TokenBucket};
+
+    #[test]
+    fn test_bucket_allows_one_second_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        assert_eq!(bucket.reserve(1000, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        bucket.reserve(1000, start);
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.reserve(250, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(100, later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_bucket_never_saves_more_than_a_second() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(2000, later), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_bucket_unlimited_and_rate_change() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0, start);
+        assert_eq!(bucket.reserve(u32::MAX as u64, start), Duration::ZERO);
+
+        bucket.set_rate(2000, start);
+        assert_eq!(bucket.rate(), 2000);
+        assert_eq!(bucket.reserve(1000, start), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_bucket_rate_change_forgives_debt() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1_000_000, start);
+        // Borrow ten seconds' worth at the original rate
+        bucket.reserve(11_000_000, start);
+        bucket.set_rate(1000, start);
+        bucket.set_rate(1_000_000, start);
+        // The next write waits for its own bytes only
+        assert_eq!(bucket.reserve(500_000, start), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_bucket_refunds_short_write() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        bucket.reserve(1000, start);
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+        // Only 100 of the 500 bytes were written
+        bucket.refund(400);
+        assert_eq!(bucket.reserve(100, start), Duration::from_millis(200));
+        // Refunds never save more than a second's worth
+        bucket.refund(5000);
+        assert_eq!(bucket.reserve(1500, start), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("5M").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_rate(" 500K\n").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("0").unwrap(), 0);
+        assert_eq!(parse_rate("off").unwrap(), 0);
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_throttled_writer_splits_large_writes() {
+        let throttle = Throttle::new(64 * 1024);
+        let mut output = Vec::new();
+        let mut writer = ThrottledWriter::new(&mut output, Some(&throttle));
+        let written = writer.write(&[1u8; 32 * 1024]).unwrap();
+        assert_eq!(written, 16 * 1024);
+
+        let mut output = Vec::new();
+        let mut writer = ThrottledWriter::new(&mut output, None);
+        assert_eq!(writer.write(&[1u8; 32 * 1024]).unwrap(), 32 * 1024);
+    }
+
+    #[test]
+    fn test_throttled_writer_limits_rate() {
+        let throttle = Throttle::new(40 * 1024);
+        let mut output = Vec::new();
+        let start = Instant::now();
+        // One second of burst, then another 10 KiB at 40 KiB/s
+        ThrottledWriter::new(&mut output, Some(&throttle))
+            .write_all(&[0u8; 50 * 1024])
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(output.len(), 50 * 1024);
+    }
+
+    #[test]
+    fn test_control_file_sets_rate() {
+        let path = std::env::temp_dir().join(format!("flash-rate-{}", std::process::id()));
+        fs::write(&path, "2M\n").unwrap();
+        let throttle = Throttle::with_control_file(1024, &path);
+        assert_eq!(throttle.rate(), 2 * 1024 * 1024);
+
+        // An unreadable rate leaves the current one in place
+        let path_invalid = path.with_extension("invalid");
+        fs::write(&path_invalid, "fast").unwrap();
+        let throttle = Throttle::with_control_file(1024, &path_invalid);
+        assert_eq!(throttle.rate(), 1024);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(path_invalid).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(test)]
+mod tests {
+    use flash::units::{parse_duration, parse_size};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("5M").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_size("1.5m").unwrap(), 1536 * 1024);
+        assert_eq!(parse_size("50G").unwrap(), 50 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("50GB").unwrap(), parse_size("50GiB").unwrap());
+    }
+
+    #[test]
+    fn test_parse_size_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-5M").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("15m").unwrap(), 15 * 60);
+        assert_eq!(parse_duration("12h").unwrap(), 12 * 3600);
+        assert_eq!(parse_duration("30d").unwrap(), 30 * 86400);
+        assert_eq!(parse_duration("2w").unwrap(), 14 * 86400);
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1y").is_err());
+        assert!(parse_duration("9999999999999999d").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+}
@@ -1,8 +1,20 @@
 #[cfg(test)]
 mod tests {
+    use flash::compress::CompressionFormat;
     use flash::upload;
     use std::fs::{self, File};
-    use std::io::Write;
+    use std::io::{self, Cursor, Read, Write};
+
+    fn remote_stat(uid: u32, gid: u32) -> ssh2::FileStat {
+        ssh2::FileStat {
+            size: Some(10),
+            uid: Some(uid),
+            gid: Some(gid),
+            perm: Some(0o100600),
+            atime: None,
+            mtime: None,
+        }
+    }
 
     #[test]
     fn test_upload_via_sftp_local_mock() {
@@ -20,4 +32,179 @@ mod tests {
         assert!(result.is_err());
         fs::remove_file(test_file).unwrap();
     }
+
+    #[test]
+    fn test_chunk_ranges_split_evenly() {
+        let chunks = upload::chunk_ranges(100, 4, 10);
+        assert_eq!(chunks, vec![0..25, 25..50, 50..75, 75..100]);
+    }
+
+    #[test]
+    fn test_chunk_ranges_cover_uneven_sizes() {
+        let chunks = upload::chunk_ranges(103, 4, 10);
+        assert_eq!(chunks.first().unwrap().start, 0);
+        assert_eq!(chunks.last().unwrap().end, 103);
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_chunk_ranges_respect_minimum_size() {
+        // Only two chunks of at least 40 bytes fit
+        assert_eq!(upload::chunk_ranges(100, 8, 40), vec![0..50, 50..100]);
+        // Small files and a single connection stay in one piece
+        assert_eq!(upload::chunk_ranges(30, 8, 40), vec![0..30]);
+        assert_eq!(upload::chunk_ranges(100, 1, 10), vec![0..100]);
+        assert_eq!(upload::chunk_ranges(0, 4, 10), vec![0..0]);
+    }
+
+    #[test]
+    fn test_pipelined_copy_copies_everything() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut output = Vec::new();
+        let mut reported = 0;
+        let copied = upload::pipelined_copy(Cursor::new(data.clone()), &mut output, 4096, |n| {
+            assert!(n <= 4096);
+            reported += n;
+        })
+        .unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(reported, data.len() as u64);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_pipelined_copy_empty_input() {
+        let mut output = Vec::new();
+        let copied = upload::pipelined_copy(Cursor::new(Vec::new()), &mut output, 4096, |_| {
+            panic!("nothing should be written")
+        })
+        .unwrap();
+        assert_eq!(copied, 0);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_pipelined_copy_stops_on_write_error() {
+        struct Failing;
+        impl Write for Failing {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let data = vec![0u8; 1 << 20];
+        let result = upload::pipelined_copy(Cursor::new(data), &mut Failing, 1024, |_| {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_copy_compressed_round_trips() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 7) as u8).collect();
+
+        let mut plain = Vec::new();
+        let read = upload::copy_compressed(
+            Cursor::new(data.clone()),
+            &mut plain,
+            None,
+            "",
+            4096,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(read, data.len() as u64);
+        assert_eq!(plain, data);
+
+        let mut gzipped = Vec::new();
+        let mut counted = 0;
+        upload::copy_compressed(
+            Cursor::new(data.clone()),
+            &mut gzipped,
+            Some(CompressionFormat::TarGz),
+            "",
+            4096,
+            |n| counted += n,
+        )
+        .unwrap();
+        assert_eq!(counted, data.len() as u64);
+        assert!(gzipped.len() < data.len());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let mut zipped = Vec::new();
+        upload::copy_compressed(
+            Cursor::new(data.clone()),
+            &mut zipped,
+            Some(CompressionFormat::Zip),
+            "db.sql",
+            4096,
+            |_| {},
+        )
+        .unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(zipped)).unwrap();
+        assert_eq!(archive.len(), 1);
+        let mut entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.name(), "db.sql");
+        let mut decoded = Vec::new();
+        entry.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        assert!(upload::copy_compressed(
+            Cursor::new(data),
+            &mut Vec::new(),
+            Some(CompressionFormat::Tar),
+            "",
+            4096,
+            |_| {}
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_attribute_stat_keeps_existing_group_for_uid_only() {
+        let options = upload::UploadOptions {
+            uid: Some(1001),
+            ..Default::default()
+        };
+        let stat = upload::attribute_stat(&options, Some(&remote_stat(500, 600)));
+        assert_eq!((stat.uid, stat.gid), (Some(1001), Some(600)));
+        assert_eq!(stat.perm, Some(0o644));
+
+        // Without the current attributes the ownership is not touched
+        let stat = upload::attribute_stat(&options, None);
+        assert_eq!((stat.uid, stat.gid), (None, None));
+        assert_eq!(stat.perm, Some(0o644));
+    }
+
+    #[test]
+    fn test_attribute_stat_keeps_existing_owner_for_gid_only() {
+        let options = upload::UploadOptions {
+            gid: Some(1001),
+            ..Default::default()
+        };
+        let stat = upload::attribute_stat(&options, Some(&remote_stat(500, 600)));
+        assert_eq!((stat.uid, stat.gid), (Some(500), Some(1001)));
+
+        let stat = upload::attribute_stat(&options, None);
+        assert_eq!((stat.uid, stat.gid), (None, None));
+    }
+
+    #[test]
+    fn test_attribute_stat_uses_both_ids() {
+        let options = upload::UploadOptions {
+            uid: Some(1),
+            gid: Some(2),
+            ..Default::default()
+        };
+        let stat = upload::attribute_stat(&options, None);
+        assert_eq!((stat.uid, stat.gid), (Some(1), Some(2)));
+        let stat = upload::attribute_stat(&upload::UploadOptions::default(), None);
+        assert_eq!((stat.uid, stat.gid), (None, None));
+    }
 }
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use flash::compress::{self, CompressionFormat};
+    use flash::loading::TransferProgress;
+    use flash::verify::{self, TreeListing};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Source tree of incompressible data, so corrupting the archive hits
+    /// entry contents.
+    fn scratch_source(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("flash-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let source = base.join("source");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        let data: Vec<u8> = (0..64 * 1024u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        fs::write(source.join("data.bin"), &data).unwrap();
+        fs::write(source.join("nested/b.txt"), "def").unwrap();
+        (base, source)
+    }
+
+    fn archive(base: &Path, source: &Path, format: CompressionFormat) -> PathBuf {
+        let archive = base.join(format!("source.{}", format.extension()));
+        compress::compress(
+            &source.to_string_lossy(),
+            &archive.to_string_lossy(),
+            format,
+        )
+        .unwrap();
+        archive
+    }
+
+    fn verify(archive: &Path, format: CompressionFormat) -> anyhow::Result<TreeListing> {
+        let progress = TransferProgress::new();
+        let result = verify::verify_archive(archive, format, &progress);
+        progress.finish_and_clear();
+        result
+    }
+
+    #[test]
+    fn test_verify_matches_source() {
+        let (base, source) = scratch_source("verify");
+        let source_listing = verify::source_listing(&source).unwrap();
+        assert_eq!(
+            source_listing.get(Path::new("nested/b.txt")),
+            Some(&Some(3))
+        );
+        assert_eq!(source_listing.get(Path::new("nested")), Some(&None));
+
+        for format in [
+            CompressionFormat::Zip,
+            CompressionFormat::Tar,
+            CompressionFormat::TarGz,
+        ] {
+            let archive = archive(&base, &source, format);
+            let listing = verify(&archive, format).unwrap();
+            let diff = verify::compare_listings(&listing, &source_listing);
+            assert!(diff.is_empty(), "{}: {}", format.name(), diff.describe());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let (base, source) = scratch_source("verify-corrupt");
+        for format in [CompressionFormat::Zip, CompressionFormat::TarGz] {
+            let archive = archive(&base, &source, format);
+            let mut bytes = fs::read(&archive).unwrap();
+            let middle = bytes.len() / 2;
+            bytes[middle] ^= 0xff;
+            fs::write(&archive, &bytes).unwrap();
+            assert!(verify(&archive, format).is_err(), "{}", format.name());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_verify_checks_gzip_trailer() {
+        let (base, source) = scratch_source("verify-trailer");
+        let archive = archive(&base, &source, CompressionFormat::TarGz);
+        let mut bytes = fs::read(&archive).unwrap();
+        // The trailer is the CRC-32 followed by the uncompressed size
+        let crc = bytes.len() - 8;
+        bytes[crc] ^= 0xff;
+        fs::write(&archive, &bytes).unwrap();
+        assert!(verify(&archive, CompressionFormat::TarGz).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_verify_detects_truncation() {
+        let (base, source) = scratch_source("verify-truncated");
+        let archive = archive(&base, &source, CompressionFormat::Tar);
+        let bytes = fs::read(&archive).unwrap();
+        // Cut inside the contents of data.bin
+        fs::write(&archive, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(verify(&archive, CompressionFormat::Tar).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_compare_listings() {
+        let archive = TreeListing::from([
+            (PathBuf::from("a.txt"), Some(3)),
+            (PathBuf::from("b.txt"), Some(5)),
+            (PathBuf::from("extra.txt"), Some(1)),
+        ]);
+        let source = TreeListing::from([
+            (PathBuf::from("a.txt"), Some(3)),
+            (PathBuf::from("b.txt"), Some(4)),
+            (PathBuf::from("dir"), None),
+        ]);
+        let diff = verify::compare_listings(&archive, &source);
+        assert_eq!(diff.missing, vec![PathBuf::from("dir")]);
+        assert_eq!(diff.unexpected, vec![PathBuf::from("extra.txt")]);
+        assert_eq!(diff.size_mismatches, vec![(PathBuf::from("b.txt"), 5, 4)]);
+    }
+
+    #[test]
+    fn test_bundle_matches_sources() {
+        let (base, source) = scratch_source("verify-bundle");
+        let readme = base.join("README.md");
+        fs::write(&readme, "readme").unwrap();
+        let inputs = vec![source.clone(), readme];
+        let names = vec!["source".to_string(), "README.md".to_string()];
+
+        let source_listing = verify::bundle_listing(&inputs, &names).unwrap();
+        assert_eq!(source_listing.get(Path::new("source")), Some(&None));
+        assert_eq!(
+            source_listing.get(Path::new("source/nested/b.txt")),
+            Some(&Some(3))
+        );
+        assert_eq!(source_listing.get(Path::new("README.md")), Some(&Some(6)));
+
+        for format in [
+            CompressionFormat::Zip,
+            CompressionFormat::Tar,
+            CompressionFormat::TarGz,
+        ] {
+            let archive = base.join(format!("bundle.{}", format.extension()));
+            compress::compress_bundle(&inputs, &names, &archive.to_string_lossy(), format).unwrap();
+            let listing = verify(&archive, format).unwrap();
+            let diff = verify::compare_listings(&listing, &source_listing);
+            assert!(diff.is_empty(), "{}: {}", format.name(), diff.describe());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+}